@@ -3,8 +3,12 @@ fn main() {
     cc::Build::new()
         .file("src/signals.c")
         .file("src/lua_runner.c")
+        .file("src/limits.c")
+        .file("src/condition.c")
         .include("/usr/include/lua5.4/")
         .compile("sig");
     println!("cargo:rerun-if-changed=src/signals.c");
     println!("cargo:rerun-if-changed=src/lua_runner.c");
+    println!("cargo:rerun-if-changed=src/limits.c");
+    println!("cargo:rerun-if-changed=src/condition.c");
 }
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+#[cfg(debug_assertions)]
+use crate::log::*;
+
+
+static COMMAND_HASH: Mutex<Option<CommandHash>> = Mutex::new(None);
+
+/// Index of the executables in `PATH`. It is rebuilt whenever `PATH` changes
+/// or one of its directories is modified.
+struct CommandHash {
+    path: String,
+    dir_mtimes: Vec<Option<SystemTime>>,
+    /// Executable name to the first `PATH` directory containing it.
+    executables: HashMap<String, String>,
+    /// Hit counts of the commands that have been run or hashed explicitly.
+    hits: HashMap<String, usize>,
+}
+
+impl CommandHash {
+    fn build(path: &str) -> Self {
+        let mut executables = HashMap::new();
+
+        for dir in path.split(":").filter(|d| !d.is_empty()) {
+            if let Ok(entries) = std::fs::read_dir(dir) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    executables.entry(entry.file_name().to_string_lossy().to_string()).or_insert(dir.to_string());
+                }
+            }
+        }
+
+        log!(LogLevel::Debug, "Hashed {} executables", executables.len());
+
+        Self {
+            path: path.to_string(),
+            dir_mtimes: Self::get_mtimes(path),
+            executables,
+            hits: HashMap::new(),
+        }
+    }
+
+    fn get_mtimes(path: &str) -> Vec<Option<SystemTime>> {
+        path.split(":").map(|dir| std::fs::metadata(dir).and_then(|m| m.modified()).ok()).collect()
+    }
+
+    fn is_stale(&self, path: &str) -> bool {
+        self.path != path || self.dir_mtimes != Self::get_mtimes(path)
+    }
+
+    fn get_path(&self, name: &str) -> Option<String> {
+        self.executables.get(name).map(|dir| format!("{}/{}", dir.trim_end_matches('/'), name))
+    }
+}
+
+fn with_hash<T>(f: impl FnOnce(&mut CommandHash) -> T) -> T {
+    let path = std::env::var("PATH").unwrap_or_default();
+    let mut hash = COMMAND_HASH.lock().unwrap_or_else(|e| e.into_inner());
+
+    if hash.as_ref().is_none_or(|h| h.is_stale(&path)) {
+        *hash = Some(CommandHash::build(&path));
+    }
+
+    f(hash.as_mut().unwrap())
+}
+
+/// Returns the full path of the executable `name` in `PATH`.
+pub fn lookup(name: &str) -> Option<String> {
+    with_hash(|h| h.get_path(name))
+}
+
+/// Like `lookup`, but remembers the command and counts the hit.
+pub fn hit(name: &str) -> Option<String> {
+    with_hash(|h| {
+        let path = h.get_path(name)?;
+        *h.hits.entry(name.to_string()).or_insert(0) += 1;
+        Some(path)
+    })
+}
+
+/// Remembers `name` without counting a hit, as done by `hash name`.
+pub fn remember(name: &str) -> Option<String> {
+    with_hash(|h| {
+        let path = h.get_path(name)?;
+        h.hits.entry(name.to_string()).or_insert(0);
+        Some(path)
+    })
+}
+
+pub fn is_remembered(name: &str) -> bool {
+    with_hash(|h| h.hits.contains_key(name))
+}
+
+/// Returns the remembered commands as `(name, path, hits)`, sorted by name.
+pub fn get_remembered() -> Vec<(String, String, usize)> {
+    with_hash(|h| {
+        let mut remembered: Vec<_> = h.hits.iter()
+            .filter_map(|(name, hits)| Some((name.to_string(), h.get_path(name)?, *hits)))
+            .collect();
+        remembered.sort();
+        remembered
+    })
+}
+
+/// Returns every executable in `PATH` along with the directory it is found in.
+pub fn get_executables() -> Vec<(String, String)> {
+    with_hash(|h| h.executables.iter().map(|(n, d)| (n.to_string(), d.to_string())).collect())
+}
+
+pub fn get_executables_with_prefix(prefix: &str) -> Vec<String> {
+    with_hash(|h| h.executables.keys().filter(|n| n.starts_with(prefix)).cloned().collect())
+}
+
+/// Forgets everything, forcing the index to be rebuilt on the next lookup.
+pub fn rehash() {
+    *COMMAND_HASH.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
@@ -1,15 +1,21 @@
 
 use std::env::current_dir;
-use crate::parser;
+use crate::{
+    parser,
+    command_hash,
+    frecency,
+};
 
 #[derive(Debug)]
 pub enum PosibilityType {
     Executable,
     File,
+    FrecentDirectory,
     ProgramSpecific
 }
 
 const CMD_SIM_THRESHOLD: f64 = 0.9;
+const FRECENT_DIR_COMMAND: &str = "z";
 
 
 pub fn get_possibilities<'a>(string: &'a str, cursor_pos: u16) -> (&'a str, String, Vec<String>) {
@@ -21,6 +27,10 @@ pub fn get_possibilities<'a>(string: &'a str, cursor_pos: u16) -> (&'a str, Stri
         PosibilityType::File => {
             get_files(to_replace)
         },
+        PosibilityType::FrecentDirectory => {
+            // Ranked rather than sorted, so there's no common prefix to complete
+            return get_frecent_dirs(string, cursor_pos);
+        },
         PosibilityType::ProgramSpecific => {
             get_files(to_replace)
         }
@@ -88,13 +98,9 @@ fn get_similar_commands<'a>(command: &'a str) -> (String, String, Vec<String>) {
     );
 
     // Check directories in PATH
-    if let Ok(env_path) = std::env::var("PATH") {
-        env_path.split(":").for_each(|dir| {
-            similar_commands.append(
-                &mut get_similar_commands_in_dir(dir, command)
-            );
-        })
-    }
+    similar_commands.append(
+        &mut command_hash::get_executables_with_prefix(command)
+    );
 
     // Check current directory
     similar_commands.append(
@@ -109,6 +115,21 @@ fn get_similar_commands<'a>(command: &'a str) -> (String, String, Vec<String>) {
 }
 
 
+/// Completes the arguments of `z` to the matching directories, best first.
+/// All arguments are replaced, as they are fragments rather than a path.
+fn get_frecent_dirs(string: &str, cursor_pos: u16) -> (&str, String, Vec<String>) {
+    let line = &string[..cursor_pos as usize];
+    let args = line.trim_start()[FRECENT_DIR_COMMAND.len()..].trim_start();
+    let fragments: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+
+    let dirs = frecency::query(&fragments)
+        .iter()
+        .map(|e| reverse_tilde(&e.path).replace(" ", "\\ "))
+        .collect();
+
+    (args, String::new(), dirs)
+}
+
 fn get_command_specific_options<'a>(string: &'a str, cursor_pos: u16) -> (&'a str, String, Vec<String>) {
     
     let to_complete = get_string_at(string, cursor_pos);
@@ -146,6 +167,8 @@ fn get_possibility_type(string: &str, cursor_pos: u16) -> PosibilityType {
     
     if is_command_completion(string, cursor_pos) {
         return PosibilityType::Executable;
+    } else if string.split_whitespace().next() == Some(FRECENT_DIR_COMMAND) {
+        return PosibilityType::FrecentDirectory;
     } else if is_file_completion(string, cursor_pos) {
         return PosibilityType::File;
     } else {
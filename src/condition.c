@@ -0,0 +1,28 @@
+#include <regex.h>
+
+
+/* Matches `string` against the extended regular expression `pattern`, storing
+ * the offsets of the match and its groups, up to `max`, in `starts` and `ends`
+ * (-1 for groups that did not participate). Returns the number of offsets
+ * stored on a match, 0 if there is none and -1 if `pattern` is invalid. */
+int regex_match(const char *pattern, const char *string, int max, int *starts, int *ends)
+{
+    regex_t regex;
+    regmatch_t matches[max];
+
+    if (regcomp(&regex, pattern, REG_EXTENDED) != 0)
+        return -1;
+
+    int groups = regex.re_nsub + 1 < (size_t)max ? (int)regex.re_nsub + 1 : max;
+    int found = regexec(&regex, string, groups, matches, 0) == 0;
+    regfree(&regex);
+
+    if (!found)
+        return 0;
+
+    for (int i = 0; i < groups; i++) {
+        starts[i] = matches[i].rm_so;
+        ends[i] = matches[i].rm_eo;
+    }
+    return groups;
+}
@@ -0,0 +1,559 @@
+use std::{
+    ffi::{c_char, CString},
+    fs::{self, Metadata},
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+};
+
+
+extern "C" {
+    fn access(path: *const c_char, mode: i32) -> i32;
+    fn isatty(fd: i32) -> i32;
+    fn geteuid() -> u32;
+    fn getegid() -> u32;
+    fn fnmatch(pattern: *const c_char, string: *const c_char, flags: i32) -> i32;
+    fn regex_match(pattern: *const c_char, string: *const c_char, max: i32, starts: *mut i32, ends: *mut i32) -> i32;
+}
+
+const R_OK: i32 = 4;
+const W_OK: i32 = 2;
+const X_OK: i32 = 1;
+
+const UNARY_OPERATORS: [&str; 21] = [
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-p",
+    "-r", "-s", "-S", "-t", "-u", "-w", "-x", "-z", "-G",
+];
+const BINARY_OPERATORS: [&str; 12] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-ef",
+];
+const FILE_COMPARISONS: [&str; 2] = ["-nt", "-ot"];
+const REGEX_MATCH: &str = "=~";
+const MAX_REGEX_GROUPS: usize = 32;
+/// The characters special in globs or regular expressions, which match
+/// literally when quoted in a pattern.
+const PATTERN_CHARACTERS: &str = "\\*?[](){}.+|^$";
+
+
+/// Evaluates the arguments of `test` (without the closing `]` of `[`).
+/// Errors are syntax errors, for which `test` exits with 2.
+pub fn test(args: &[String]) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut parser = TestParser { args: &args, pos: 0 };
+
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    let result = parser.or()?;
+    match parser.peek() {
+        None => Ok(result),
+        Some(arg) => Err(format!("{}: unexpected argument", arg)),
+    }
+}
+
+struct TestParser<'s> {
+    args: &'s [&'s str],
+    pos: usize,
+}
+
+impl<'s> TestParser<'s> {
+    fn peek(&self) -> Option<&'s str> {
+        self.args.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'s str> {
+        let arg = self.peek();
+        self.pos += 1;
+        arg
+    }
+
+    fn remaining(&self) -> usize {
+        self.args.len().saturating_sub(self.pos)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek() == Some("-o") && self.remaining() > 1 {
+            self.pos += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek() == Some("-a") && self.remaining() > 1 {
+            self.pos += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        // A lone `!` is just a non-empty string
+        if self.peek() == Some("!") && self.remaining() > 1 && !self.is_binary_at(self.pos + 1) {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn is_binary_at(&self, pos: usize) -> bool {
+        pos + 1 < self.args.len()
+            && (BINARY_OPERATORS.contains(&self.args[pos]) || FILE_COMPARISONS.contains(&self.args[pos]))
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(arg) = self.next() else {
+            return Err("argument expected".to_string());
+        };
+
+        // Binary operators take precedence, so that `[ -n = -n ]` compares strings
+        if self.is_binary_at(self.pos) {
+            let operator = self.next().unwrap();
+            let rhs = self.next().unwrap();
+            return binary(arg, operator, rhs);
+        }
+
+        if arg == "(" && self.remaining() > 1 {
+            let result = self.or()?;
+            return match self.next() {
+                Some(")") => Ok(result),
+                _ => Err("`)' expected".to_string()),
+            };
+        }
+
+        if UNARY_OPERATORS.contains(&arg) && self.remaining() > 0 {
+            let operand = self.next().unwrap();
+            return unary(arg, operand);
+        }
+
+        Ok(!arg.is_empty())
+    }
+}
+
+fn unary(operator: &str, operand: &str) -> Result<bool, String> {
+    match operator {
+        "-n" => return Ok(!operand.is_empty()),
+        "-z" => return Ok(operand.is_empty()),
+        "-t" => {
+            let fd: i32 = operand.parse().map_err(|_| format!("{}: integer expression expected", operand))?;
+            return Ok(unsafe { isatty(fd) } == 1);
+        },
+        "-r" => return Ok(accessible(operand, R_OK)),
+        "-w" => return Ok(accessible(operand, W_OK)),
+        "-x" => return Ok(accessible(operand, X_OK)),
+        _ => (),
+    }
+
+    // Symbolic links are only not followed when testing for them
+    let metadata = match operator {
+        "-h" | "-L" => fs::symlink_metadata(operand),
+        _ => fs::metadata(operand),
+    };
+    let Ok(metadata) = metadata else {
+        return Ok(false);
+    };
+
+    Ok(file_test(operator, &metadata))
+}
+
+/// The file tests that only need `metadata`.
+fn file_test(operator: &str, metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    let mode = metadata.permissions().mode();
+
+    match operator {
+        "-e" => true,
+        "-f" => file_type.is_file(),
+        "-d" => file_type.is_dir(),
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-h" | "-L" => file_type.is_symlink(),
+        "-s" => metadata.len() > 0,
+        "-u" => mode & 0o4000 != 0,
+        "-g" => mode & 0o2000 != 0,
+        "-k" => mode & 0o1000 != 0,
+        "-O" => metadata.uid() == unsafe { geteuid() },
+        "-G" => metadata.gid() == unsafe { getegid() },
+        _ => false,
+    }
+}
+
+fn accessible(path: &str, mode: i32) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn binary(lhs: &str, operator: &str, rhs: &str) -> Result<bool, String> {
+    let integer = |s: &str| -> Result<i64, String> {
+        s.trim().parse().map_err(|_| format!("{}: integer expression expected", s))
+    };
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    Ok(match operator {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" => integer(lhs)? == integer(rhs)?,
+        "-ne" => integer(lhs)? != integer(rhs)?,
+        "-lt" => integer(lhs)? < integer(rhs)?,
+        "-le" => integer(lhs)? <= integer(rhs)?,
+        "-gt" => integer(lhs)? > integer(rhs)?,
+        "-ge" => integer(lhs)? >= integer(rhs)?,
+        "-nt" => match (modified(lhs), modified(rhs)) {
+            (Some(l), Some(r)) => l > r,
+            (l, r) => l.is_some() && r.is_none(),
+        },
+        "-ot" => match (modified(lhs), modified(rhs)) {
+            (Some(l), Some(r)) => l < r,
+            (l, r) => l.is_none() && r.is_some(),
+        },
+        "-ef" => match (fs::metadata(lhs), fs::metadata(rhs)) {
+            (Ok(l), Ok(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+            _ => false,
+        },
+        _ => return Err(format!("{}: binary operator expected", operator)),
+    })
+}
+
+/// A word of `[[ ... ]]` after expansion.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    /// The text with its quoted characters escaped, for use as a pattern.
+    pub pattern: String,
+    /// Whether any part of the word was quoted, making e.g. `"!"` a plain string.
+    pub quoted: bool,
+}
+
+impl Word {
+    fn push(&mut self, text: &str, quoted: bool) {
+        for c in text.chars() {
+            if quoted && PATTERN_CHARACTERS.contains(c) {
+                self.pattern.push('\\');
+            }
+            self.pattern.push(c);
+        }
+        self.text.push_str(text);
+        self.quoted |= quoted;
+    }
+
+    fn is_operator(&self, operator: &str) -> bool {
+        !self.quoted && self.text == operator
+    }
+}
+
+/// Splits what is between `[[` and `]]` into words, passing the unquoted and
+/// double quoted parts through `expand`. Unlike for commands, expansions are
+/// neither split nor globbed, so an empty variable still makes a word.
+pub fn split_words(s: &str, expand: &dyn Fn(&str) -> String) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut word = Word::default();
+    let mut in_word = false;
+    // Unquoted text yet to be expanded
+    let mut unquoted = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            word.push(&expand(&unquoted), false);
+            unquoted.clear();
+            if in_word {
+                words.push(std::mem::take(&mut word));
+                in_word = false;
+            }
+            continue;
+        }
+        in_word = true;
+
+        match c {
+            '\\' | '\'' | '"' => {
+                word.push(&expand(&unquoted), false);
+                unquoted.clear();
+            },
+            _ => (),
+        }
+
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => word.push(&c.to_string(), true),
+                None => word.push("\\", false),
+            },
+            '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => text.push(c),
+                        None => return Err("unexpected EOF while looking for matching `''".to_string()),
+                    }
+                }
+                word.push(&text, true);
+            },
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek().is_some_and(|c| "$`\"\\".contains(*c)) => {
+                            word.push(&expand(&text), true);
+                            text.clear();
+                            word.push(&chars.next().unwrap().to_string(), true);
+                        },
+                        Some('$') if chars.peek() == Some(&'(') => {
+                            text.push('$');
+                            take_substitution(&mut chars, &mut text)?;
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err("unexpected EOF while looking for matching `\"'".to_string()),
+                    }
+                }
+                word.push(&expand(&text), true);
+            },
+            '$' if chars.peek() == Some(&'(') => {
+                unquoted.push('$');
+                take_substitution(&mut chars, &mut unquoted)?;
+            },
+            c => unquoted.push(c),
+        }
+    }
+
+    word.push(&expand(&unquoted), false);
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Moves a command substitution's `( ... )`, including any whitespace
+/// within, from `chars` to `text`.
+fn take_substitution(chars: &mut impl Iterator<Item = char>, text: &mut String) -> Result<(), String> {
+    let mut depth = 0;
+    for c in chars {
+        text.push(c);
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Ok(()),
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+    Err("unexpected EOF while looking for matching `)'".to_string())
+}
+
+/// Evaluates the words of `[[ ... ]]`, without the brackets. Each evaluated
+/// `=~` stores its match and groups in `rematch`, empty if it failed.
+pub fn conditional(words: &[Word], rematch: &mut Option<Vec<String>>) -> Result<bool, String> {
+    if words.is_empty() {
+        return Err("expression expected".to_string());
+    }
+    let mut parser = ConditionalParser { words, pos: 0, evaluate: true, rematch };
+
+    let result = parser.or()?;
+    match parser.peek() {
+        None => Ok(result),
+        Some(word) => Err(format!("{}: unexpected argument", word.text)),
+    }
+}
+
+struct ConditionalParser<'s> {
+    words: &'s [Word],
+    pos: usize,
+    /// Cleared while skipping the right side of a short-circuited `&&` or `||`.
+    evaluate: bool,
+    rematch: &'s mut Option<Vec<String>>,
+}
+
+impl<'s> ConditionalParser<'s> {
+    fn peek(&self) -> Option<&'s Word> {
+        self.words.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'s Word> {
+        let word = self.peek();
+        self.pos += 1;
+        word
+    }
+
+    fn at(&self, operator: &str) -> bool {
+        self.peek().is_some_and(|w| w.is_operator(operator))
+    }
+
+    /// Parses the right side of `&&` or `||`, only evaluating it if `needed`.
+    fn skip_unless(&mut self, needed: bool, side: fn(&mut Self) -> Result<bool, String>) -> Result<bool, String> {
+        let evaluate = self.evaluate;
+        self.evaluate &= needed;
+        let result = side(self);
+        self.evaluate = evaluate;
+        result
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.at("||") {
+            self.pos += 1;
+            result |= self.skip_unless(!result, Self::and)?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.at("&&") {
+            self.pos += 1;
+            result &= self.skip_unless(result, Self::not)?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.at("!") {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(word) = self.next() else {
+            return Err("expression expected".to_string());
+        };
+
+        if word.is_operator("(") {
+            let result = self.or()?;
+            return match self.next() {
+                Some(word) if word.is_operator(")") => Ok(result),
+                _ => Err("`)' expected".to_string()),
+            };
+        }
+        if ["&&", "||", ")"].iter().any(|operator| word.is_operator(operator)) {
+            return Err(format!("{}: unexpected argument", word.text));
+        }
+
+        let is_binary = |w: &Word| !w.quoted && (
+            BINARY_OPERATORS.contains(&w.text.as_str())
+            || FILE_COMPARISONS.contains(&w.text.as_str())
+            || w.text == REGEX_MATCH
+        );
+        if let Some(operator) = self.peek().filter(|w| is_binary(w)) {
+            self.pos += 1;
+            let Some(rhs) = self.next() else {
+                return Err(format!("{}: argument expected", operator.text));
+            };
+            return self.binary(word, &operator.text, rhs);
+        }
+
+        if !word.quoted && UNARY_OPERATORS.contains(&word.text.as_str()) {
+            let Some(operand) = self.next() else {
+                return Err(format!("{}: argument expected", word.text));
+            };
+            return if self.evaluate { unary(&word.text, &operand.text) } else { Ok(false) };
+        }
+
+        Ok(!word.text.is_empty())
+    }
+
+    /// Like `binary`, but the right side of `==`, `!=` and `=~` is a pattern.
+    fn binary(&mut self, lhs: &Word, operator: &str, rhs: &Word) -> Result<bool, String> {
+        if !self.evaluate {
+            return Ok(false);
+        }
+        match operator {
+            "=" | "==" => Ok(glob_match(&rhs.pattern, &lhs.text)),
+            "!=" => Ok(!glob_match(&rhs.pattern, &lhs.text)),
+            REGEX_MATCH => {
+                let groups = regex(&rhs.pattern, &lhs.text)?;
+                let matched = groups.is_some();
+                *self.rematch = Some(groups.unwrap_or_default());
+                Ok(matched)
+            },
+            _ => binary(&lhs.text, operator, &rhs.text),
+        }
+    }
+}
+
+/// Whether `text` matches the glob `pattern` as a whole.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    match (CString::new(pattern), CString::new(text)) {
+        (Ok(pattern), Ok(text)) => unsafe { fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 },
+        _ => false,
+    }
+}
+
+/// Matches `text` against the extended regular expression `pattern`,
+/// returning the match followed by its groups, which are empty if unset.
+fn regex(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let (Ok(c_pattern), Ok(c_text)) = (CString::new(pattern), CString::new(text)) else {
+        return Ok(None);
+    };
+    let mut starts = [0; MAX_REGEX_GROUPS];
+    let mut ends = [0; MAX_REGEX_GROUPS];
+
+    let found = unsafe {
+        regex_match(c_pattern.as_ptr(), c_text.as_ptr(), MAX_REGEX_GROUPS as i32, starts.as_mut_ptr(), ends.as_mut_ptr())
+    };
+    if found < 0 {
+        return Err(format!("{}: invalid regular expression", pattern));
+    }
+
+    // Offsets are in bytes and may split a character
+    let bytes = text.as_bytes();
+    Ok((found > 0).then(|| (0..found as usize)
+        .map(|i| match (usize::try_from(starts[i]), usize::try_from(ends[i])) {
+            (Ok(start), Ok(end)) => String::from_utf8_lossy(&bytes[start..end]).to_string(),
+            _ => String::new(),
+        })
+        .collect()))
+}
+
+
+#[test]
+fn test_test() {
+    let test = |args: &[&str]| test(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+
+    assert_eq!(test(&[]), Ok(false));
+    assert_eq!(test(&["x"]), Ok(true));
+    assert_eq!(test(&["-n"]), Ok(true));
+    assert_eq!(test(&["-z", ""]), Ok(true));
+    assert_eq!(test(&["!", "-z", ""]), Ok(false));
+    assert_eq!(test(&["-n", "=", "-n"]), Ok(true));
+    assert_eq!(test(&["2", "-lt", "10", "-a", "b", ">", "a"]), Ok(true));
+    assert_eq!(test(&["(", "1", "-eq", "2", "-o", "x", ")", "-a", "!", ""]), Ok(true));
+    assert_eq!(test(&["-d", "/", "-a", "-e", "/nonexistent"]), Ok(false));
+    assert!(test(&["a", "-lt", "1"]).is_err());
+    assert!(test(&["a", "b"]).is_err());
+}
+
+#[test]
+fn test_conditional() {
+    let expand = |s: &str| s.replace("$x", "a b");
+    let test = |s: &str| conditional(&split_words(s, &expand)?, &mut None);
+
+    assert_eq!(split_words("-n $x", &expand).unwrap().len(), 2);
+    assert_eq!(split_words("a'*'\\?\"$x\"", &expand).unwrap()[0].pattern, "a\\*\\?a b");
+    assert_eq!(split_words("$(echo a b) ''", &expand).unwrap().len(), 2);
+    assert_eq!(test("$x == a*"), Ok(true));
+    assert_eq!(test("$x == 'a*'"), Ok(false));
+    assert_eq!(test("abc != a?[bc]"), Ok(false));
+    assert_eq!(test("b > a && ! -z $x"), Ok(true));
+    assert_eq!(test("( 1 -eq 2 || -d / ) && '' == ''"), Ok(true));
+    assert_eq!(test("'!'"), Ok(true));
+    assert!(test("a -lt 1").is_err());
+    assert!(test("a ==").is_err());
+    assert!(test("'a").is_err());
+
+    let mut rematch = None;
+    let words = split_words("ab12 =~ ^([a-z]+)([0-9])(x)?", &expand).unwrap();
+    assert_eq!(conditional(&words, &mut rematch), Ok(true));
+    assert_eq!(rematch.take().unwrap(), ["ab1", "ab", "1", ""]);
+    let words = split_words("a_b =~ 'a.' || c =~ c || a =~ (a)", &expand).unwrap();
+    assert_eq!(conditional(&words[..3], &mut rematch), Ok(false));
+    assert_eq!(rematch, Some(vec![]));
+    assert_eq!(conditional(&words, &mut rematch), Ok(true));
+    assert_eq!(rematch.unwrap(), ["c"]);
+}
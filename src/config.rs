@@ -1,6 +1,12 @@
 use std::collections::HashMap;
 use crate::log::*;
 
+pub const SYSTEM_CONFIG_DIR: &str = "/etc/luabster";
+pub const CONFIG_FILE: &str = "config.lua";
+pub const RC_FILE: &str = "luabster.conf";
+pub const PROFILE_FILE: &str = "profile";
+pub const LOGOUT_FILE: &str = "logout";
+
 pub type Parser = fn (&str) -> Option<ConfigType>;
 pub type Configs<'a> = HashMap<&'a str, ConfigType>;
 pub type ConfigParam<'a> = (&'a str, &'a dyn Configuration);
@@ -41,6 +47,32 @@ pub fn configure<'a, 'b, T: ConfigurationLoader<'a, 'b>>(configurables: &'a mut
     log!(LogLevel::Debug, "Configs: {:?}", configs);
 }
 
+/// Returns the user's config directory. `$XDG_CONFIG_HOME/luabster` is used
+/// if it exists, otherwise the config lives in `~/.luabster`.
+pub fn user_config_dir(home_dir: &str) -> String {
+    let xdg_config_home = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or(format!("{}/.config", home_dir));
+    let xdg_dir = format!("{}/luabster", xdg_config_home);
+
+    if std::path::Path::new(&xdg_dir).is_dir() {
+        xdg_dir
+    } else {
+        format!("{}/.luabster", home_dir)
+    }
+}
+
+/// Returns every existing copy of the config file `name`, system-wide layer first,
+/// so that user settings override system ones.
+pub fn config_files(home_dir: &str, name: &str) -> Vec<String> {
+    [SYSTEM_CONFIG_DIR.to_string(), user_config_dir(home_dir)]
+        .iter()
+        .map(|dir| format!("{}/{}", dir, name))
+        .filter(|f| std::path::Path::new(f).is_file())
+        .collect()
+}
+
 pub trait Configurable<'a> {
     fn get_configs(&self) -> &'a [ConfigParam<'a>];
     fn with_config(&mut self, configs: &Configs);
@@ -0,0 +1,85 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+};
+
+
+/// Default name of a `coproc` started without one.
+pub const DEFAULT_NAME: &str = "COPROC";
+
+
+/// A command running alongside the shell, connected to it by a pipe on each
+/// of its stdin and stdout.
+#[derive(Debug)]
+pub struct Coprocess {
+    pub pid: i32,
+    /// Writes to the coprocess' stdin, `None` once closed.
+    input: Option<File>,
+    /// Reads from the coprocess' stdout.
+    output: BufReader<File>,
+}
+
+impl Coprocess {
+    /// # Safety
+    /// `read_fd` and `write_fd` must be open pipe ends owned by nothing else.
+    pub unsafe fn from_fds(pid: i32, read_fd: RawFd, write_fd: RawFd) -> Self {
+        Self {
+            pid,
+            input: Some(File::from_raw_fd(write_fd)),
+            output: BufReader::new(File::from_raw_fd(read_fd)),
+        }
+    }
+
+    pub fn read_fd(&self) -> RawFd {
+        self.output.get_ref().as_raw_fd()
+    }
+
+    pub fn write_fd(&self) -> Option<RawFd> {
+        self.input.as_ref().map(|f| f.as_raw_fd())
+    }
+
+    pub fn write(&mut self, data: &str) -> io::Result<()> {
+        match self.input.as_mut() {
+            Some(input) => input.write_all(data.as_bytes()),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "coprocess input is closed")),
+        }
+    }
+
+    /// Reads a line without its newline, or `None` once the coprocess closed its stdout.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.output.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    /// Closes the coprocess' stdin, so that it sees the end of its input.
+    pub fn close(&mut self) {
+        self.input = None;
+    }
+}
+
+
+#[test]
+fn test_coprocess() {
+    use std::{os::fd::IntoRawFd, process::{Command, Stdio}};
+
+    let mut cat = Command::new("cat").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    let write_fd = cat.stdin.take().unwrap().into_raw_fd();
+    let read_fd = cat.stdout.take().unwrap().into_raw_fd();
+    let mut coproc = unsafe { Coprocess::from_fds(cat.id() as i32, read_fd, write_fd) };
+
+    coproc.write("hello\nworld\n").unwrap();
+    assert_eq!(coproc.read_line().unwrap(), Some("hello".to_string()));
+    coproc.close();
+    assert!(coproc.write("more").is_err());
+    assert_eq!(coproc.read_line().unwrap(), Some("world".to_string()));
+    assert_eq!(coproc.read_line().unwrap(), None);
+    cat.wait().unwrap();
+}
@@ -0,0 +1,175 @@
+use std::{io::{self, IsTerminal}, path::Path};
+
+use crate::{
+    command_hash,
+    config,
+    parser::CliParser,
+    termio,
+    tag,
+};
+
+#[cfg(debug_assertions)]
+use crate::log::*;
+
+
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+/// Commands whose operands are files they only read, which must exist. Others,
+/// like `mkdir` or `touch`, may well be given new names.
+const READING_COMMANDS: [&str; 20] = [
+    "cat", "less", "more", "head", "tail", "tac", "nl", "wc", "sort", "uniq",
+    "diff", "cmp", "file", "stat", "du", "ls", "od", "xxd", "source", ".",
+];
+
+
+impl<'a> config::Configurable<'a> for ArgumentCorrection {
+    fn get_configs(&self) -> &'a [config::ConfigParam<'a>] {
+        & tag!{"general",
+            "correct_arguments" => false,
+        }
+    }
+
+    fn with_config(&mut self, configs: &config::Configs) {
+        if let Some(config::ConfigType::Toggle(b)) = configs.get("general.correct_arguments") {
+            self.enabled = *b;
+        }
+    }
+}
+
+/// Typo correction of `cd` targets, the files of commands reading them and subcommands.
+#[derive(Clone, Debug, Default)]
+pub struct ArgumentCorrection {
+    pub enabled: bool,
+}
+
+impl ArgumentCorrection {
+    /// Offers a correction for every argument of `command` that looks mistyped,
+    /// replacing those the user confirms.
+    pub fn correct(&self, command: &mut [String]) {
+        // Nobody could answer without a terminal
+        if !self.enabled || command.is_empty() || !io::stdin().is_terminal() {
+            return;
+        }
+
+        let dirs_only = command[0] == "cd";
+        let existing_paths = dirs_only || READING_COMMANDS.contains(&command[0].as_str());
+        let subcommands = get_subcommands(&command[0]);
+        let first_operand = command.iter().skip(1).position(|a| !a.starts_with('-')).map(|i| i + 1);
+
+        for (i, arg) in command.iter_mut().enumerate().skip(1) {
+            if arg.starts_with('-') || arg.contains(GLOB_CHARS) {
+                continue;
+            }
+
+            let suggestion = if Some(i) == first_operand && !subcommands.is_empty() {
+                correct_subcommand(arg, &subcommands)
+            } else if existing_paths {
+                correct_path(arg, dirs_only)
+            } else {
+                None
+            };
+
+            if let Some(suggestion) = suggestion {
+                let question = format!("luabster: correct '{}' to '{}'? [y/n] ", arg, suggestion);
+                if termio::confirm(&question, false).unwrap_or(false) {
+                    *arg = suggestion;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the subcommands of `program` that are installed as separate executables,
+/// e.g. `git-log` for `git`.
+fn get_subcommands(program: &str) -> Vec<String> {
+    let prefix = format!("{}-", program);
+    let mut subcommands: Vec<String> = command_hash::get_executables_with_prefix(&prefix)
+        .iter()
+        .map(|e| e[prefix.len()..].to_string())
+        .collect();
+
+    // Most git subcommands live outside of PATH
+    if program == "git" {
+        if let Ok(out) = std::process::Command::new("git").arg("--exec-path").output() {
+            let exec_path = String::from_utf8_lossy(&out.stdout).trim().to_string();
+            if let Ok(entries) = std::fs::read_dir(exec_path) {
+                subcommands.extend(
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .filter_map(|entry| entry.file_name().to_string_lossy().strip_prefix(&prefix).map(str::to_string))
+                );
+            }
+        }
+    }
+
+    subcommands.sort();
+    subcommands.dedup();
+    subcommands
+}
+
+fn correct_subcommand(arg: &str, subcommands: &[String]) -> Option<String> {
+    if subcommands.iter().any(|s| s == arg) {
+        return None;
+    }
+
+    subcommands.iter()
+        .filter_map(|s| CliParser::correction(s.to_string(), "", arg))
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+        .map(|c| c.name)
+}
+
+/// Corrects a path that doesn't exist to the most similar existing one,
+/// fixing mistyped parent directories along the way.
+fn correct_path(arg: &str, dirs_only: bool) -> Option<String> {
+    if arg.is_empty() || Path::new(arg).exists() {
+        return None;
+    }
+
+    let (parent, name) = match arg.trim_end_matches('/').rsplit_once('/') {
+        Some(("", name)) => ("/".to_string(), name),
+        Some((parent, name)) => (parent.to_string(), name),
+        None => (".".to_string(), arg.trim_end_matches('/')),
+    };
+
+    let parent = if Path::new(&parent).is_dir() {
+        parent
+    } else {
+        correct_path(&parent, true)?
+    };
+
+    log!(LogLevel::Debug, "Correcting {} in {}", name, parent);
+
+    let best = CliParser::check_for_possible_corrections_in_dir(&parent, name)
+        .into_iter()
+        .filter(|c| !dirs_only || Path::new(&parent).join(&c.name).is_dir())
+        .max_by(|a, b| a.score.total_cmp(&b.score))?;
+
+    if parent == "." && !arg.starts_with("./") {
+        Some(best.name)
+    } else {
+        Some(Path::new(&parent).join(best.name).display().to_string())
+    }
+}
+
+
+#[test]
+fn test_correct_path() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("documents")).unwrap();
+    std::fs::write(dir.path().join("documents/report.txt"), "").unwrap();
+    std::fs::write(dir.path().join("reports"), "").unwrap();
+
+    let root = dir.path().display().to_string();
+
+    assert_eq!(correct_path(&format!("{}/documnets", root), false), Some(format!("{}/documents", root)));
+    assert_eq!(correct_path(&format!("{}/documnets/reprot.txt", root), false), Some(format!("{}/documents/report.txt", root)));
+    assert_eq!(correct_path(&format!("{}/reprots", root), true), None);
+    assert_eq!(correct_path(&format!("{}/documents", root), true), None);
+}
+
+#[test]
+fn test_correct_subcommand() {
+    let subcommands = ["checkout", "commit", "status"].map(str::to_string);
+
+    assert_eq!(correct_subcommand("stauts", &subcommands), Some("status".to_string()));
+    assert_eq!(correct_subcommand("status", &subcommands), None);
+}
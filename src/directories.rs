@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use crate::{
+    config,
+    tag,
+};
+
+
+const CDPATH_KEY: &str = "CDPATH";
+
+
+impl<'a> config::Configurable<'a> for Directories {
+    fn get_configs(&self) -> &'a [config::ConfigParam<'a>] {
+        & tag!{"general",
+            "auto_cd" => false,
+        }
+    }
+
+    fn with_config(&mut self, configs: &config::Configs) {
+        if let Some(config::ConfigType::Toggle(b)) = configs.get("general.auto_cd") {
+            self.auto_cd = *b;
+        }
+    }
+}
+
+/// The `pushd`/`popd` directory stack. The current directory is always the
+/// implicit top entry, so only the directories below it are stored.
+#[derive(Clone, Debug, Default)]
+pub struct Directories {
+    stack: Vec<String>,
+    pub auto_cd: bool,
+}
+
+impl Directories {
+    /// All entries as listed by `dirs`, the current directory first.
+    pub fn entries(&self, cwd: &str) -> Vec<String> {
+        let mut entries = vec![cwd.to_string()];
+        entries.extend(self.stack.iter().cloned());
+        entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+
+    /// Pushes the directory that is being left.
+    pub fn push(&mut self, dir: &str) {
+        self.stack.insert(0, dir.to_string());
+    }
+
+    /// Removes entry `n` of `entries`. The current directory can't be removed this way.
+    pub fn remove(&mut self, n: usize) -> Option<String> {
+        if n == 0 || n > self.stack.len() {
+            None
+        } else {
+            Some(self.stack.remove(n - 1))
+        }
+    }
+
+    /// Rotates the stack so that entry `n` ends up on top and returns the
+    /// directory to change to.
+    pub fn rotate(&mut self, n: usize, cwd: &str) -> Option<String> {
+        let mut entries = self.entries(cwd);
+        if n >= entries.len() {
+            return None;
+        }
+        entries.rotate_left(n);
+        let top = entries.remove(0);
+        self.stack = entries;
+        Some(top)
+    }
+
+    /// Parses a `+N`/`-N` stack index, `-N` counting from the bottom.
+    pub fn parse_index(&self, arg: &str) -> Option<usize> {
+        let len = self.stack.len() + 1;
+        let n: usize = arg.get(1..)?.parse().ok()?;
+        if n >= len {
+            return None;
+        }
+        match arg.chars().next()? {
+            '+' => Some(n),
+            '-' => Some(len - 1 - n),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_tilde(dir: &str, home_dir: &str) -> String {
+    if dir == "~" {
+        home_dir.to_string()
+    } else if let Some(rest) = dir.strip_prefix("~/") {
+        format!("{}/{}", home_dir, rest)
+    } else {
+        dir.to_string()
+    }
+}
+
+/// Replaces the home directory prefix of `dir` with `~`.
+pub fn contract_tilde(dir: &str, home_dir: &str) -> String {
+    match dir.strip_prefix(home_dir) {
+        Some(rest) if !home_dir.is_empty() && (rest.is_empty() || rest.starts_with('/')) => format!("~{}", rest),
+        _ => dir.to_string(),
+    }
+}
+
+/// Looks `dir` up in `$CDPATH`. Returns the directory found and whether it
+/// came from a `CDPATH` entry other than the current directory, in which
+/// case `cd` prints it.
+pub fn search_cdpath(dir: &str) -> (String, bool) {
+    if dir.starts_with('/') || dir.starts_with("./") || dir.starts_with("../") || dir == "." || dir == ".." {
+        return (dir.to_string(), false);
+    }
+
+    if let Ok(cdpath) = std::env::var(CDPATH_KEY) {
+        for entry in cdpath.split(':') {
+            if entry.is_empty() || entry == "." {
+                if Path::new(dir).is_dir() {
+                    return (dir.to_string(), false);
+                }
+                continue;
+            }
+
+            let candidate = Path::new(entry).join(dir);
+            if candidate.is_dir() {
+                return (candidate.display().to_string(), true);
+            }
+        }
+    }
+
+    (dir.to_string(), false)
+}
+
+
+#[test]
+fn test_dir_stack() {
+    let mut dirs = Directories::default();
+    dirs.push("/b");
+    dirs.push("/a");
+    assert_eq!(dirs.entries("/cwd"), ["/cwd", "/a", "/b"]);
+
+    assert_eq!(dirs.parse_index("+1"), Some(1));
+    assert_eq!(dirs.parse_index("-0"), Some(2));
+    assert_eq!(dirs.parse_index("+3"), None);
+
+    assert_eq!(dirs.rotate(2, "/cwd"), Some("/b".to_string()));
+    assert_eq!(dirs.entries("/b"), ["/b", "/cwd", "/a"]);
+
+    assert_eq!(dirs.remove(1), Some("/cwd".to_string()));
+    assert_eq!(dirs.remove(0), None);
+    assert_eq!(dirs.entries("/b"), ["/b", "/a"]);
+}
+
+#[test]
+fn test_tilde() {
+    assert_eq!(expand_tilde("~/src", "/home/u"), "/home/u/src");
+    assert_eq!(expand_tilde("a~b", "/home/u"), "a~b");
+    assert_eq!(contract_tilde("/home/u/src", "/home/u"), "~/src");
+    assert_eq!(contract_tilde("/home/user2", "/home/u"), "/home/user2");
+}
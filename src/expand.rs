@@ -17,6 +17,76 @@ pub fn expand_bash(s: &str) -> Result<String, Box<dyn std::error::Error>> {
     Ok(s)
 }
 
+/// Expands the variables in `s` outside single quotes, e.g. `$name`, `${name}`,
+/// `${name:-default}`, `$1` or `$#`, with the values `lookup` gives. Quotes and
+/// backslashes are kept for splitting the words later. Fails with the name of
+/// the first variable `lookup` has no value for.
+pub fn expand_variables(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    expand_variables_with(s, true, lookup)
+}
+
+/// Like `expand_variables`, for text whose quotes were already removed.
+pub fn expand_unquoted_variables(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    expand_variables_with(s, false, lookup)
+}
+
+fn expand_variables_with(s: &str, quotes: bool, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(s.len());
+    let (mut single_quoted, mut double_quoted) = (false, false);
+    let mut i = 0;
+
+    while let Some(c) = s[i..].chars().next() {
+        i += c.len_utf8();
+        match c {
+            '\'' if quotes && !double_quoted => single_quoted = !single_quoted,
+            '"' if quotes && !single_quoted => double_quoted = !double_quoted,
+            '\\' if quotes && !single_quoted => if let Some(escaped) = s[i..].chars().next() {
+                expanded.push(c);
+                expanded.push(escaped);
+                i += escaped.len_utf8();
+                continue;
+            },
+            '$' if !single_quoted => if let Some((name, default, len)) = variable_at(&s[i..]) {
+                match (lookup(name), default) {
+                    (Some(value), None) => expanded.push_str(&value),
+                    (Some(value), Some(_)) if !value.is_empty() => expanded.push_str(&value),
+                    (_, Some(default)) => expanded.push_str(default),
+                    (None, None) => return Err(name.to_string()),
+                }
+                i += len;
+                continue;
+            },
+            _ => (),
+        }
+        expanded.push(c);
+    }
+
+    Ok(expanded)
+}
+
+/// The variable referenced at the start of `s`, just after a `$`: its name, the
+/// default of `${name:-default}` and the length of the reference.
+fn variable_at(s: &str) -> Option<(&str, Option<&str>, usize)> {
+    if let Some(braced) = s.strip_prefix('{') {
+        let end = braced.find('}')?;
+        let (name, default) = match braced[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&braced[..end], None),
+        };
+        let special = name.len() == 1 && "#@*".contains(name);
+        let valid = special || name.bytes().all(|b| b.is_ascii_digit())
+            || name.starts_with(|c: char| !c.is_ascii_digit()) && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+        return (valid && !name.is_empty()).then_some((name, default, end + 2));
+    }
+
+    let first = s.chars().next()?;
+    if first.is_ascii_digit() || "#@*".contains(first) {
+        return Some((&s[..1], None, 1));
+    }
+    let len = s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len());
+    (len > 0).then_some((&s[..len], None, len))
+}
+
 fn find_expansion(s: &str) -> Option<&str> {
     if let Some(idx) = s.find("$(") {
         let start_slice = &s[idx..];
@@ -27,3 +97,20 @@ fn find_expansion(s: &str) -> Option<&str> {
     None
 }
 
+
+
+#[test]
+fn test_expand_variables() {
+    let lookup = |name: &str| match name {
+        "HOME" => Some("/home/me".to_string()),
+        "1" => Some("first".to_string()),
+        "#" => Some("1".to_string()),
+        "EMPTY" => Some(String::new()),
+        _ => None,
+    };
+
+    assert_eq!(expand_variables("cd $HOME/src \"${HOME}\"", &lookup), Ok("cd /home/me/src \"/home/me\"".to_string()));
+    assert_eq!(expand_variables("echo '$UNSET' \\$UNSET \"'$1'\" $#", &lookup), Ok("echo '$UNSET' \\$UNSET \"'first'\" 1".to_string()));
+    assert_eq!(expand_variables("echo ${UNSET:-none} ${EMPTY:-none} $ $(pwd)", &lookup), Ok("echo none none $ $(pwd)".to_string()));
+    assert_eq!(expand_variables("echo \"$UNSET\"", &lookup), Err("UNSET".to_string()));
+}
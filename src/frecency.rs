@@ -0,0 +1,243 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(debug_assertions)]
+use crate::log::*;
+
+
+const DIR_DB_FILE: &str = ".luabster/.dirs";
+/// Once the ranks add up to more than this, all of them are aged.
+const MAX_TOTAL_RANK: f64 = 9000.0;
+const AGING_FACTOR: f64 = 0.99;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// A visited directory. The file stores one per line as `path|rank|time`,
+/// the same format as `z`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub path: String,
+    pub rank: f64,
+    pub last_visit: u64,
+}
+
+impl DirEntry {
+    /// Rank weighted by how recently the directory was visited.
+    pub fn frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_visit);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        };
+
+        self.rank * weight
+    }
+
+    /// Whether the fragments occur in the path in order, the last one within
+    /// the final path component. Matching is case insensitive.
+    fn matches(&self, fragments: &[String]) -> bool {
+        let path = self.path.to_lowercase();
+        let mut rest = path.as_str();
+
+        for fragment in fragments {
+            let fragment = fragment.to_lowercase();
+            match rest.find(&fragment) {
+                Some(i) => rest = &rest[i + fragment.len()..],
+                None => return false,
+            }
+        }
+
+        match fragments.last() {
+            Some(last) => {
+                let last_component = path.rsplit('/').next().unwrap_or("");
+                last_component.contains(&last.to_lowercase())
+            },
+            None => true
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DirDb {
+    path: String,
+    entries: Vec<DirEntry>,
+}
+
+impl DirDb {
+    fn load(path: &str) -> Self {
+        let read = || -> io::Result<String> {
+            let mut file = File::open(path)?;
+            file.lock_shared()?;
+            let mut content = String::new();
+            file.read_to_string(&mut content)?;
+            Ok(content)
+        };
+
+        Self::parse(path, &read().unwrap_or_default())
+    }
+
+    fn parse(path: &str, content: &str) -> Self {
+        Self { path: path.to_string(), entries: content.lines().filter_map(Self::parse_line).collect() }
+    }
+
+    fn parse_line(line: &str) -> Option<DirEntry> {
+        let mut fields = line.rsplitn(3, '|');
+        let last_visit = fields.next()?.parse().ok()?;
+        let rank = fields.next()?.parse().ok()?;
+        let path = fields.next()?.to_string();
+
+        Some(DirEntry { path, rank, last_visit })
+    }
+
+    /// Runs `f` on the database at `path` and saves it, all with the file locked.
+    /// It is read anew first, so that the visits other shells recorded are kept.
+    fn update<T>(path: &str, f: impl FnOnce(&mut Self) -> T) -> io::Result<T> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        file.lock()?;
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let mut db = Self::parse(path, &content);
+        let result = f(&mut db);
+
+        let content: String = db.entries.iter()
+            .map(|e| format!("{}|{}|{}\n", e.path, e.rank, e.last_visit))
+            .collect();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(content.as_bytes())?;
+
+        Ok(result)
+    }
+
+    fn add(&mut self, dir: &str, now: u64) {
+        match self.entries.iter_mut().find(|e| e.path == dir) {
+            Some(entry) => {
+                entry.rank += 1.0;
+                entry.last_visit = now;
+            },
+            None => self.entries.push(DirEntry { path: dir.to_string(), rank: 1.0, last_visit: now }),
+        }
+
+        if self.entries.iter().map(|e| e.rank).sum::<f64>() > MAX_TOTAL_RANK {
+            for entry in self.entries.iter_mut() {
+                entry.rank *= AGING_FACTOR;
+            }
+            self.entries.retain(|e| e.rank >= 1.0);
+        }
+    }
+
+    fn query(&self, fragments: &[String], now: u64) -> Vec<DirEntry> {
+        let mut matches: Vec<DirEntry> = self.entries.iter()
+            .filter(|e| e.matches(fragments))
+            .cloned()
+            .collect();
+
+        matches.sort_by(|a, b| b.frecency(now).total_cmp(&a.frecency(now)));
+        matches
+    }
+}
+
+fn db_path() -> String {
+    let home_dir = home::home_dir().map(|p| p.display().to_string()).unwrap_or_default();
+    format!("{}/{}", home_dir, DIR_DB_FILE)
+}
+
+/// Changes the database, which is shared by all running shells.
+fn update<T: Default>(f: impl FnOnce(&mut DirDb) -> T) -> T {
+    let path = db_path();
+    match DirDb::update(&path, f) {
+        Ok(result) => result,
+        Err(e) => {
+            log!(LogLevel::Error, "Unable to save {}: {:?}", path, e);
+            T::default()
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Records a visit to `dir`.
+pub fn add(dir: &str) {
+    update(|db| db.add(dir, now()));
+}
+
+/// Forgets `dir`, returning whether it was known.
+pub fn remove(dir: &str) -> bool {
+    update(|db| {
+        let len = db.entries.len();
+        db.entries.retain(|e| e.path != dir);
+        db.entries.len() != len
+    })
+}
+
+/// The existing directories matching `fragments`, best match first.
+pub fn query(fragments: &[String]) -> Vec<DirEntry> {
+    DirDb::load(&db_path()).query(fragments, now())
+        .into_iter()
+        .filter(|e| std::path::Path::new(&e.path).is_dir())
+        .collect()
+}
+
+/// The frecency of `entry` as of now.
+pub fn score(entry: &DirEntry) -> f64 {
+    entry.frecency(now())
+}
+
+
+#[test]
+fn test_query() {
+    let mut db = DirDb::default();
+    let now = 10 * WEEK;
+
+    db.add("/home/u/src/luabster", now - 2 * WEEK);
+    db.add("/home/u/src/luabster", now - 2 * WEEK);
+    db.add("/home/u/src/luabster/src", now);
+    db.add("/home/u/Documents", now);
+
+    let paths = |fragments: &[&str]| -> Vec<String> {
+        let fragments: Vec<String> = fragments.iter().map(|f| f.to_string()).collect();
+        db.query(&fragments, now).into_iter().map(|e| e.path).collect()
+    };
+
+    assert_eq!(paths(&["lua"]), ["/home/u/src/luabster"]);
+    assert_eq!(paths(&["lua", "src"]), ["/home/u/src/luabster/src"]);
+    assert_eq!(paths(&["doc"]), ["/home/u/Documents"]);
+    assert_eq!(paths(&["src"]), ["/home/u/src/luabster/src"]);
+    assert_eq!(paths(&[]).len(), 3);
+    assert_eq!(paths(&[])[2], "/home/u/src/luabster");
+}
+
+#[test]
+fn test_parse_line() {
+    assert_eq!(
+        DirDb::parse_line("/tmp/a|b|2.5|1700000000"),
+        Some(DirEntry { path: "/tmp/a|b".to_string(), rank: 2.5, last_visit: 1700000000 })
+    );
+    assert_eq!(DirDb::parse_line("garbage"), None);
+}
+
+#[test]
+fn test_update() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dirs").display().to_string();
+
+    // Every change starts from the file, which other shells may have changed
+    DirDb::update(&path, |db| db.add("/a", 1)).unwrap();
+    DirDb::update(&path, |db| db.add("/b", 2)).unwrap();
+
+    let paths: Vec<String> = DirDb::load(&path).entries.into_iter().map(|e| e.path).collect();
+    assert_eq!(paths, ["/a", "/b"]);
+}
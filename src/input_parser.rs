@@ -1,14 +1,13 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use crate::{
-    parser::Errors,
-    tag,
-};
+use std::io;
+use crate::tag;
 
 #[cfg(debug_assertions)]
 use crate::log::*;
 use crate::termio;
 use crate::config;
+use crate::line_editor;
 
 use itertools::Itertools;
 
@@ -45,9 +44,16 @@ impl<'a> config::Configurable<'a> for InputParser {
     fn get_configs(&self) -> &'a [config::ConfigParam<'a>] {
         &tag!{
             "input",
+            "word_chars" => line_editor::DEFAULT_WORD_CHARS,
+            "edit_mode" => line_editor::DEFAULT_EDIT_MODE,
             ,{"history",
                 "length" => 1000,
             },
+            {"vi",
+                "insert_indicator" => line_editor::DEFAULT_INSERT_INDICATOR,
+                "normal_indicator" => line_editor::DEFAULT_NORMAL_INDICATOR,
+                "visual_indicator" => line_editor::DEFAULT_VISUAL_INDICATOR,
+            },
         }
     }
 
@@ -58,13 +64,32 @@ impl<'a> config::Configurable<'a> for InputParser {
                 _ => (),
             }
         }
+
+        let mut editor = termio::editor();
+        if let Some(config::ConfigType::String(chars)) = configs.get("input.word_chars") {
+            editor.word_chars = chars.clone();
+        }
+        if let Some(config::ConfigType::String(mode)) = configs.get("input.edit_mode") {
+            match line_editor::EditMode::from_name(mode) {
+                Some(mode) => editor.edit_mode = mode,
+                None => {
+                    log!(LogLevel::Error, "Unknown edit mode: {}", mode);
+                },
+            }
+        }
+        for (i, mode) in ["insert", "normal", "visual"].iter().enumerate() {
+            if let Some(config::ConfigType::String(indicator)) = configs.get(format!("input.vi.{}_indicator", mode).as_str()) {
+                editor.indicators[i] = indicator.clone();
+            }
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct InputParser {
     history: VecDeque<String>,
-    history_path: String
+    history_path: String,
+    abbreviations: HashMap<String, String>,
 }
 
 impl InputParser {
@@ -72,10 +97,28 @@ impl InputParser {
     pub fn new(home_dir: &str) -> Self {
         Self {
             history: VecDeque::new(),
-            history_path: format!("{}/{}", home_dir, HISTORY_FILE)
+            history_path: format!("{}/{}", home_dir, HISTORY_FILE),
+            abbreviations: HashMap::new(),
         }
     }
 
+    pub fn get_abbreviation(&self, name: &str) -> Option<String> {
+        self.abbreviations.get(name).cloned()
+    }
+
+    /// Returns all abbreviations sorted by name.
+    pub fn get_abbreviations(&self) -> Vec<(String, String)> {
+        self.abbreviations.iter().map(|(n, e)| (n.to_string(), e.to_string())).sorted().collect()
+    }
+
+    pub fn set_abbreviation(&mut self, name: &str, expansion: &str) {
+        self.abbreviations.insert(name.to_string(), expansion.to_string());
+    }
+
+    pub fn remove_abbreviation(&mut self, name: &str) -> bool {
+        self.abbreviations.remove(name).is_some()
+    }
+
     fn load_history(&mut self, max_history_len: usize) {
         if let Ok(content) = fs::read_to_string(&self.history_path) {
             self.history = content.split("\n").map(|substr| substr.to_owned()).take(max_history_len).collect();
@@ -96,7 +139,12 @@ impl InputParser {
         let mut scope = 0;
         
         loop {
-            let mut input = self.get_line();
+            let mut input = match self.get_line() {
+                Ok(input) => input,
+                // Ctrl-D on an empty line exits, Ctrl-C abandons the whole input
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && full_input.is_empty() => return "exit".to_string(),
+                Err(_) => return String::new(),
+            };
             let new_line_expected = new_line_expected(&mut input, &mut scope);
 
             full_input.push_str(&input);
@@ -111,18 +159,14 @@ impl InputParser {
         return full_input;
     }
 
-    pub fn check_quit(&self, input: &str) -> Result<(), Errors> {
-        if input == "exit" {
-            match self.save_history() {
-                Err(e) => println!("Unable to save history: {:?}", e),
-                Ok(_) => {
-                    log!(LogLevel::Debug, "Saved history to {}!", self.history_path);
-                }
-            };
-            Err(Errors::Exit) 
-        } else {
-            Ok(())
-        }
+    /// Saves the history for the next session.
+    pub fn write_history(&self) {
+        match self.save_history() {
+            Err(e) => println!("Unable to save history: {:?}", e),
+            Ok(_) => {
+                log!(LogLevel::Debug, "Saved history to {}!", self.history_path);
+            }
+        };
     }
 
     pub fn replace_last(&mut self, rep: &str) {
@@ -130,11 +174,11 @@ impl InputParser {
         self.history.push_front(rep.to_string());
     }
 
-    fn get_line(&mut self) -> String {
+    fn get_line(&mut self) -> termio::Result<String> {
 
-        let input = termio::get_line(None, &mut self.history, true).unwrap();
+        let input = termio::get_line(None, &mut self.history, true, Some(&self.abbreviations))?;
 
-        return input.trim().to_string();
+        return Ok(input.trim().to_string());
     }
 
 }
@@ -0,0 +1,71 @@
+#include <sys/resource.h>
+#include <sys/stat.h>
+
+
+/* The resource limited by the `ulimit` option `option`, or -1. */
+static int limit_resource(char option)
+{
+    switch (option) {
+    case 'c': return RLIMIT_CORE;
+    case 'd': return RLIMIT_DATA;
+    case 'e': return RLIMIT_NICE;
+    case 'f': return RLIMIT_FSIZE;
+    case 'i': return RLIMIT_SIGPENDING;
+    case 'l': return RLIMIT_MEMLOCK;
+    case 'm': return RLIMIT_RSS;
+    case 'n': return RLIMIT_NOFILE;
+    case 'q': return RLIMIT_MSGQUEUE;
+    case 'r': return RLIMIT_RTPRIO;
+    case 's': return RLIMIT_STACK;
+    case 't': return RLIMIT_CPU;
+    case 'u': return RLIMIT_NPROC;
+    case 'v': return RLIMIT_AS;
+    default: return -1;
+    }
+}
+
+/* Gets the soft or hard limit of `option`, -1 meaning unlimited. Returns -1
+ * on failure. */
+int get_limit(char option, int hard, long long *value)
+{
+    int resource = limit_resource(option);
+    struct rlimit limit;
+
+    if (resource < 0 || getrlimit(resource, &limit) < 0)
+        return -1;
+
+    rlim_t v = hard ? limit.rlim_max : limit.rlim_cur;
+    *value = v == RLIM_INFINITY ? -1 : (long long)v;
+    return 0;
+}
+
+/* Sets the soft and/or hard limit of `option`, -1 meaning unlimited. Returns
+ * -1 on failure. */
+int set_limit(char option, int soft, int hard, long long value)
+{
+    int resource = limit_resource(option);
+    struct rlimit limit;
+
+    if (resource < 0 || getrlimit(resource, &limit) < 0)
+        return -1;
+
+    rlim_t v = value < 0 ? RLIM_INFINITY : (rlim_t)value;
+    if (soft)
+        limit.rlim_cur = v;
+    if (hard)
+        limit.rlim_max = v;
+
+    return setrlimit(resource, &limit);
+}
+
+int get_file_mask()
+{
+    mode_t mask = umask(0);
+    umask(mask);
+    return mask;
+}
+
+void set_file_mask(int mask)
+{
+    umask(mask);
+}
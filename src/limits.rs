@@ -0,0 +1,133 @@
+use std::io;
+
+
+extern "C" {
+    fn get_limit(option: std::ffi::c_char, hard: i32, value: *mut i64) -> i32;
+    fn set_limit(option: std::ffi::c_char, soft: i32, hard: i32, value: i64) -> i32;
+    fn get_file_mask() -> i32;
+    fn set_file_mask(mask: i32);
+}
+
+/// The limits known to `ulimit` as `(option, description, unit in bytes)`,
+/// in `ulimit -a` order.
+pub const LIMITS: [(char, &str, i64); 14] = [
+    ('c', "core file size (blocks)", 1024),
+    ('d', "data seg size (kbytes)", 1024),
+    ('e', "scheduling priority", 1),
+    ('f', "file size (blocks)", 1024),
+    ('i', "pending signals", 1),
+    ('l', "max locked memory (kbytes)", 1024),
+    ('m', "max memory size (kbytes)", 1024),
+    ('n', "open files", 1),
+    ('q', "POSIX message queues (bytes)", 1),
+    ('r', "real-time priority", 1),
+    ('s', "stack size (kbytes)", 1024),
+    ('t', "cpu time (seconds)", 1),
+    ('u', "max user processes", 1),
+    ('v', "virtual memory (kbytes)", 1024),
+];
+
+
+fn unit_of(option: char) -> i64 {
+    LIMITS.iter().find(|(o, _, _)| *o == option).map_or(1, |(_, _, unit)| *unit)
+}
+
+/// The soft or hard limit for `option` in its unit, `None` if unlimited.
+pub fn get(option: char, hard: bool) -> io::Result<Option<i64>> {
+    let mut value = 0;
+    if unsafe { get_limit(option as std::ffi::c_char, hard.into(), &mut value) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((value >= 0).then(|| value / unit_of(option)))
+}
+
+/// Sets the soft and/or hard limit for `option`, given in its unit.
+pub fn set(option: char, soft: bool, hard: bool, value: Option<i64>) -> io::Result<()> {
+    let value = value.map_or(-1, |v| v.saturating_mul(unit_of(option)));
+    if unsafe { set_limit(option as std::ffi::c_char, soft.into(), hard.into(), value) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn umask() -> u32 {
+    unsafe { get_file_mask() as u32 }
+}
+
+pub fn set_umask(mask: u32) {
+    unsafe { set_file_mask(mask as i32) }
+}
+
+/// Parses a `umask` argument, either an octal mask or a symbolic mode like
+/// `u=rwx,g+r,o-w` describing the permissions left unmasked.
+pub fn parse_umask(mode: &str, current: u32) -> Option<u32> {
+    if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        return u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o777);
+    }
+
+    let mut allowed = !current & 0o777;
+    for clause in mode.split(',') {
+        let op_idx = clause.find(['=', '+', '-'])?;
+        let (who, rest) = clause.split_at(op_idx);
+        let (op, perms) = rest.split_at(1);
+
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who.is_empty() {
+            who_mask = 0o777;
+        }
+
+        let mut perm_bits = 0;
+        for c in perms.chars() {
+            perm_bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None,
+            };
+        }
+        let bits = perm_bits & who_mask;
+
+        match op {
+            "=" => allowed = (allowed & !who_mask) | bits,
+            "+" => allowed |= bits,
+            _ => allowed &= !bits,
+        }
+    }
+
+    Some(!allowed & 0o777)
+}
+
+/// The permissions left unmasked by `mask`, as `umask -S` prints them.
+pub fn symbolic_umask(mask: u32) -> String {
+    let allowed = !mask & 0o777;
+    let perms = |shift: u32| -> String {
+        [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')].iter()
+            .filter(|(bit, _)| (allowed >> shift) & bit != 0)
+            .map(|(_, c)| *c)
+            .collect()
+    };
+
+    format!("u={},g={},o={}", perms(6), perms(3), perms(0))
+}
+
+
+#[test]
+fn test_umask() {
+    assert_eq!(parse_umask("027", 0o022), Some(0o027));
+    assert_eq!(parse_umask("u=rwx,g=rx,o=", 0o022), Some(0o027));
+    assert_eq!(parse_umask("g+w", 0o022), Some(0o002));
+    assert_eq!(parse_umask("o-r", 0o022), Some(0o026));
+    assert_eq!(parse_umask("u=rwz", 0o022), None);
+    assert_eq!(parse_umask("999", 0o022), None);
+    assert_eq!(symbolic_umask(0o027), "u=rwx,g=rx,o=");
+}
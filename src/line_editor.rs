@@ -0,0 +1,952 @@
+use std::{collections::VecDeque, ops::Range};
+
+use crate::termio::{KeyCode, KeyEvent, KeyModifiers};
+
+
+const KILL_RING_SIZE: usize = 16;
+/// The characters besides letters and digits making up words by default.
+pub const DEFAULT_WORD_CHARS: &str = "_";
+pub const DEFAULT_EDIT_MODE: &str = "emacs";
+pub const DEFAULT_INSERT_INDICATOR: &str = "[I] ";
+pub const DEFAULT_NORMAL_INDICATOR: &str = "[N] ";
+pub const DEFAULT_VISUAL_INDICATOR: &str = "[V] ";
+/// Stands for the mode indicator in the last line of a custom prompt.
+pub const MODE_PLACEHOLDER: &str = "{mode}";
+const MAX_COUNT: usize = 9999;
+
+
+/// What `termio::get_line` should do after a key was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing besides the editing already done.
+    Edit,
+    /// Insert a typed character, after expanding abbreviations for a space.
+    Insert(char),
+    Accept,
+    Complete,
+    HistoryPrevious,
+    HistoryNext,
+    ClearScreen,
+    /// Ctrl-D on an empty line.
+    Eof,
+    /// Ctrl-C, abandoning the line.
+    Interrupt,
+}
+
+/// The keymap lines are edited with, set by `input.edit_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Emacs,
+    Vi,
+}
+
+impl EditMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "emacs" => Some(Self::Emacs),
+            "vi" => Some(Self::Vi),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    Insert,
+    Normal,
+    Visual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    /// `w`, or `W` when the flag is set, taking any non-blank characters as a word.
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    Start,
+    FirstNonBlank,
+    End,
+    /// `f`, `F`, `t` or `T`: the character, whether to search forward and
+    /// whether to stop next to the character.
+    Find(char, bool, bool),
+    /// `;`, or `,` searching in the opposite direction.
+    RepeatFind(bool),
+    /// The whole line, for `dd`, `cc` and `yy`.
+    Line,
+    /// The selected text in visual mode.
+    Selection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Move(Motion),
+    Operate(Operator, Motion),
+    /// `i`, `a`, `I` or `A`.
+    Insert(char),
+    Replace(char),
+    ToggleCase,
+    /// `p`, or `P` putting the text before the cursor.
+    Put(bool),
+    Undo,
+    Redo,
+    Repeat,
+    Visual,
+    SwapEnds,
+    Action(Action),
+}
+
+/// The line being edited, with the cursor as a byte offset into it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineBuffer {
+    pub line: String,
+    pub cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn new(line: &str) -> Self {
+        Self { line: line.to_string(), cursor: line.len() }
+    }
+
+    /// Replaces the line, moving the cursor to its end.
+    pub fn set(&mut self, line: &str) {
+        *self = Self::new(line);
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.line.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Removes `range` from the line, leaving the cursor at its start.
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        self.cursor = range.start;
+        self.line.drain(range).collect()
+    }
+
+    /// The cursor's column relative to the start of the line.
+    pub fn visual_cursor(&self) -> usize {
+        self.line[..self.cursor].chars().count()
+    }
+
+    /// The position of the character before `pos`, or `pos` at the start.
+    pub fn previous(&self, pos: usize) -> usize {
+        self.line[..pos].chars().next_back().map_or(pos, |c| pos - c.len_utf8())
+    }
+
+    /// The position after the character at `pos`, or `pos` at the end.
+    pub fn next(&self, pos: usize) -> usize {
+        self.line[pos..].chars().next().map_or(pos, |c| pos + c.len_utf8())
+    }
+
+    /// The start of the word before `pos`.
+    pub fn word_start(&self, pos: usize, is_word: &dyn Fn(char) -> bool) -> usize {
+        self.line[..pos]
+            .trim_end_matches(|c| !is_word(c))
+            .trim_end_matches(is_word)
+            .len()
+    }
+
+    /// The end of the word after `pos`.
+    pub fn word_end(&self, pos: usize, is_word: &dyn Fn(char) -> bool) -> usize {
+        let rest = self.line[pos..]
+            .trim_start_matches(|c| !is_word(c))
+            .trim_start_matches(is_word);
+        self.line.len() - rest.len()
+    }
+}
+
+/// Killed text, the most recent first.
+#[derive(Debug, Default)]
+struct KillRing {
+    entries: VecDeque<String>,
+    /// Where the last yank put its text and which entry it was, if that was the
+    /// last key, for Alt-Y to replace it with the one before.
+    yanked: Option<(Range<usize>, usize)>,
+}
+
+impl KillRing {
+    fn push(&mut self, text: String) {
+        if !text.is_empty() {
+            self.entries.push_front(text);
+            self.entries.truncate(KILL_RING_SIZE);
+        }
+    }
+}
+
+/// The state of vi mode.
+#[derive(Debug)]
+struct Vi {
+    mode: ViMode,
+    /// The keys typed so far of a command in normal or visual mode.
+    pending: Vec<char>,
+    /// The count and keys of the last change for `.`, with any text it inserted.
+    last_change: (Option<usize>, Vec<KeyEvent>),
+    /// The last change while it is still inserting text.
+    recording: Option<(Option<usize>, Vec<KeyEvent>)>,
+    repeating: bool,
+    /// The end of the selection opposite the cursor in visual mode.
+    anchor: usize,
+    /// The last `f`, `F`, `t` or `T` for `;` and `,`.
+    last_find: Option<(char, bool, bool)>,
+    undo: Vec<LineBuffer>,
+    redo: Vec<LineBuffer>,
+}
+
+/// The state of the line editor kept between lines.
+#[derive(Debug)]
+pub struct Editor {
+    pub edit_mode: EditMode,
+    /// The characters besides letters and digits making up words.
+    pub word_chars: String,
+    /// What is shown before the line in vi's insert, normal and visual mode.
+    pub indicators: [String; 3],
+    /// The last line of the prompt if it shows the mode, which the editor then
+    /// draws before the next line instead of the indicator alone.
+    pub mode_prompt: Option<String>,
+    /// The `mode_prompt` of the line being edited.
+    prompt: Option<String>,
+    kill_ring: KillRing,
+    /// Whether the last key killed text, so that a following kill joins it.
+    last_kill: bool,
+    vi: Vi,
+}
+
+impl Editor {
+    pub const fn new() -> Self {
+        Self {
+            edit_mode: EditMode::Emacs,
+            word_chars: String::new(),
+            indicators: [String::new(), String::new(), String::new()],
+            mode_prompt: None,
+            prompt: None,
+            kill_ring: KillRing { entries: VecDeque::new(), yanked: None },
+            last_kill: false,
+            vi: Vi {
+                mode: ViMode::Insert,
+                pending: Vec::new(),
+                last_change: (None, Vec::new()),
+                recording: None,
+                repeating: false,
+                anchor: 0,
+                last_find: None,
+                undo: Vec::new(),
+                redo: Vec::new(),
+            },
+        }
+    }
+
+    /// Prepares for editing a new line, which vi mode starts inserting into.
+    pub fn start_line(&mut self, buffer: &LineBuffer) {
+        // The prompt only precedes the line read right after it
+        self.prompt = self.mode_prompt.take();
+        self.vi.mode = ViMode::Insert;
+        self.vi.pending.clear();
+        self.vi.recording = None;
+        self.vi.undo = vec![buffer.clone()];
+        self.vi.redo.clear();
+    }
+
+    /// Handles `key` with the keymap of the edit mode.
+    pub fn handle(&mut self, buffer: &mut LineBuffer, key: KeyEvent) -> Action {
+        match self.edit_mode {
+            EditMode::Emacs => self.emacs(buffer, key),
+            EditMode::Vi => self.vi(buffer, key),
+        }
+    }
+
+    pub fn vi_mode(&self) -> Option<ViMode> {
+        (self.edit_mode == EditMode::Vi).then_some(self.vi.mode)
+    }
+
+    /// What to show before the line for the current vi mode.
+    pub fn mode_indicator(&self) -> &str {
+        match self.vi_mode() {
+            Some(mode) => &self.indicators[mode as usize],
+            None => "",
+        }
+    }
+
+    /// What to show before the line: the prompt's last line with the mode
+    /// indicator in place of `MODE_PLACEHOLDER`, or just the indicator.
+    pub fn line_prefix(&self) -> String {
+        match &self.prompt {
+            Some(prompt) => prompt.replace(MODE_PLACEHOLDER, self.mode_indicator()),
+            None => self.mode_indicator().to_string(),
+        }
+    }
+
+    /// The name of the current mode, `emacs` or vi's `insert`, `normal` or `visual`.
+    pub fn mode_name(&self) -> &'static str {
+        match self.vi_mode() {
+            None => "emacs",
+            Some(ViMode::Insert) => "insert",
+            Some(ViMode::Normal) => "normal",
+            Some(ViMode::Visual) => "visual",
+        }
+    }
+
+    /// The selected text in visual mode.
+    pub fn selection(&self, buffer: &LineBuffer) -> Option<Range<usize>> {
+        if self.vi_mode() != Some(ViMode::Visual) {
+            return None;
+        }
+        let anchor = buffer.line.floor_char_boundary(self.vi.anchor);
+        Some(anchor.min(buffer.cursor)..buffer.next(anchor.max(buffer.cursor)))
+    }
+
+    pub fn is_word(&self, c: char) -> bool {
+        c.is_alphanumeric() || self.word_chars.contains(c)
+    }
+
+    /// Handles `key` like readline's emacs keymap.
+    pub fn emacs(&mut self, buffer: &mut LineBuffer, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let last_kill = std::mem::take(&mut self.last_kill);
+        let yanked = self.kill_ring.yanked.take();
+        let word_chars = self.word_chars.clone();
+        let is_word = |c: char| c.is_alphanumeric() || word_chars.contains(c);
+
+        match (key.code, ctrl, alt) {
+            (KeyCode::Enter, ..) | (KeyCode::Char('j' | 'm'), true, false) => return Action::Accept,
+            (KeyCode::Tab, ..) => return Action::Complete,
+            (KeyCode::Up, ..) | (KeyCode::Char('p'), true, false) => return Action::HistoryPrevious,
+            (KeyCode::Down, ..) | (KeyCode::Char('n'), true, false) => return Action::HistoryNext,
+            (KeyCode::Char('l'), true, false) => return Action::ClearScreen,
+            (KeyCode::Char('c'), true, false) => return Action::Interrupt,
+            (KeyCode::Char('d'), true, false) if buffer.line.is_empty() => return Action::Eof,
+
+            (KeyCode::Home, ..) | (KeyCode::Char('a'), true, false) => buffer.cursor = 0,
+            (KeyCode::End, ..) | (KeyCode::Char('e'), true, false) => buffer.cursor = buffer.line.len(),
+            (KeyCode::Left, false, false) | (KeyCode::Char('b'), true, false) => buffer.cursor = buffer.previous(buffer.cursor),
+            (KeyCode::Right, false, false) | (KeyCode::Char('f'), true, false) => buffer.cursor = buffer.next(buffer.cursor),
+            (KeyCode::Left, ..) | (KeyCode::Char('b'), false, true) => buffer.cursor = buffer.word_start(buffer.cursor, &is_word),
+            (KeyCode::Right, ..) | (KeyCode::Char('f'), false, true) => buffer.cursor = buffer.word_end(buffer.cursor, &is_word),
+
+            (KeyCode::Backspace, false, false) | (KeyCode::Char('h'), true, false) => {
+                buffer.remove(buffer.previous(buffer.cursor)..buffer.cursor);
+            },
+            (KeyCode::Delete, ..) | (KeyCode::Char('d'), true, false) => {
+                buffer.remove(buffer.cursor..buffer.next(buffer.cursor));
+            },
+            (KeyCode::Char('t'), true, false) => transpose(buffer),
+            (KeyCode::Char(c @ ('u' | 'l' | 'c')), false, true) => {
+                let end = buffer.word_end(buffer.cursor, &is_word);
+                let word = &buffer.line[buffer.cursor..end];
+                let changed = match c {
+                    'u' => word.to_uppercase(),
+                    'l' => word.to_lowercase(),
+                    _ => capitalize(word, &is_word),
+                };
+                buffer.remove(buffer.cursor..end);
+                buffer.insert(&changed);
+            },
+
+            (KeyCode::Char('k'), true, false) => self.kill(buffer, buffer.cursor..buffer.line.len(), last_kill, false),
+            (KeyCode::Char('u'), true, false) => self.kill(buffer, 0..buffer.cursor, last_kill, true),
+            (KeyCode::Char('w'), true, false) => {
+                // Unlike Alt-Backspace, Ctrl-W kills up to whitespace
+                let start = buffer.line[..buffer.cursor].trim_end().trim_end_matches(|c: char| !c.is_whitespace()).len();
+                self.kill(buffer, start..buffer.cursor, last_kill, true);
+            },
+            (KeyCode::Backspace, _, true) => {
+                let start = buffer.word_start(buffer.cursor, &is_word);
+                self.kill(buffer, start..buffer.cursor, last_kill, true);
+            },
+            (KeyCode::Char('d'), false, true) => {
+                let end = buffer.word_end(buffer.cursor, &is_word);
+                self.kill(buffer, buffer.cursor..end, last_kill, false);
+            },
+            (KeyCode::Char('y'), true, false) => self.yank(buffer, 0),
+            (KeyCode::Char('y'), false, true) => {
+                if let Some((range, entry)) = yanked {
+                    buffer.remove(range);
+                    self.yank(buffer, (entry + 1) % self.kill_ring.entries.len());
+                }
+            },
+
+            (KeyCode::Char(c), false, false) => return Action::Insert(c),
+            _ => (),
+        }
+
+        Action::Edit
+    }
+
+    /// Removes `range` into the kill ring. Right after another kill, the text is
+    /// joined with the last entry instead, in front of it when killing `backwards`.
+    fn kill(&mut self, buffer: &mut LineBuffer, range: Range<usize>, last_kill: bool, backwards: bool) {
+        let killed = buffer.remove(range);
+
+        match self.kill_ring.entries.front_mut() {
+            Some(entry) if last_kill && backwards => entry.insert_str(0, &killed),
+            Some(entry) if last_kill => entry.push_str(&killed),
+            _ => self.kill_ring.push(killed),
+        }
+        self.last_kill = true;
+    }
+
+    /// Inserts the kill ring's `entry` at the cursor.
+    fn yank(&mut self, buffer: &mut LineBuffer, entry: usize) {
+        if let Some(text) = self.kill_ring.entries.get(entry) {
+            let start = buffer.cursor;
+            buffer.insert(text);
+            self.kill_ring.yanked = Some((start..buffer.cursor, entry));
+        }
+    }
+
+    /// Handles `key` like vi, starting each line in insert mode.
+    pub fn vi(&mut self, buffer: &mut LineBuffer, key: KeyEvent) -> Action {
+        if self.vi.mode == ViMode::Insert {
+            return self.vi_insert(buffer, key);
+        }
+
+        let c = match (key.code, key.modifiers.contains(KeyModifiers::CONTROL)) {
+            (KeyCode::Enter, _) | (KeyCode::Char('j' | 'm'), true) => return Action::Accept,
+            (KeyCode::Char('c'), true) => return Action::Interrupt,
+            (KeyCode::Char('d'), true) if buffer.line.is_empty() => return Action::Eof,
+            (KeyCode::Char('l'), true) => return Action::ClearScreen,
+            (KeyCode::Char('r'), true) => {
+                self.vi.pending.clear();
+                return self.run(buffer, None, Command::Redo);
+            },
+            (KeyCode::Esc, _) => {
+                // Esc cancels a partly typed command before leaving visual mode
+                if self.vi.pending.is_empty() {
+                    self.vi.mode = ViMode::Normal;
+                }
+                self.vi.pending.clear();
+                return Action::Edit;
+            },
+            (KeyCode::Up, _) => 'k',
+            (KeyCode::Down, _) => 'j',
+            (KeyCode::Left | KeyCode::Backspace, _) => 'h',
+            (KeyCode::Right, _) => 'l',
+            (KeyCode::Home, _) => '0',
+            (KeyCode::End, _) => '$',
+            (KeyCode::Delete, _) => 'x',
+            (KeyCode::Char(c), false) => c,
+            _ => return Action::Edit,
+        };
+
+        self.vi.pending.push(c);
+        let (count, command) = match parse_command(&self.vi.pending, self.vi.mode) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Action::Edit,
+            Err(()) => {
+                self.vi.pending.clear();
+                return Action::Edit;
+            },
+        };
+        let keys = std::mem::take(&mut self.vi.pending);
+        let mode = self.vi.mode;
+        let changes = matches!(command,
+            Command::Operate(Operator::Delete | Operator::Change, _) | Command::Insert(_) | Command::Replace(_) | Command::ToggleCase | Command::Put(_)
+        );
+        if changes {
+            self.vi.undo.push(buffer.clone());
+            self.vi.redo.clear();
+        }
+
+        let action = self.run(buffer, count, command);
+
+        if changes && self.vi.mode != ViMode::Insert && self.vi.undo.last().is_some_and(|b| b.line == buffer.line) {
+            self.vi.undo.pop();
+        }
+        // `.` types the keys of the change again, with a new count replacing the first one
+        if changes && mode == ViMode::Normal && !self.vi.repeating {
+            let digits = if keys[0] == '0' { 0 } else { keys.iter().take_while(|c| c.is_ascii_digit()).count() };
+            let change = (
+                keys[..digits].iter().collect::<String>().parse().ok(),
+                keys[digits..].iter().map(|c| KeyEvent::from(KeyCode::Char(*c))).collect(),
+            );
+            if self.vi.mode == ViMode::Insert {
+                self.vi.recording = Some(change);
+            } else {
+                self.vi.last_change = change;
+            }
+        }
+        if self.vi.mode != ViMode::Insert {
+            buffer.cursor = buffer.cursor.min(buffer.previous(buffer.line.len()));
+        }
+
+        action
+    }
+
+    fn vi_insert(&mut self, buffer: &mut LineBuffer, key: KeyEvent) -> Action {
+        // Terminals send Alt as Esc before the key, which vi takes as leaving insert mode
+        if let (KeyCode::Char(c), true) = (key.code, key.modifiers.contains(KeyModifiers::ALT)) {
+            self.vi_insert(buffer, KeyEvent::from(KeyCode::Esc));
+            return self.vi(buffer, KeyEvent::from(KeyCode::Char(c)));
+        }
+        if key.code != KeyCode::Esc {
+            if let Some((_, keys)) = &mut self.vi.recording {
+                keys.push(key);
+            }
+            return self.emacs(buffer, key);
+        }
+
+        self.vi.mode = ViMode::Normal;
+        buffer.cursor = buffer.previous(buffer.cursor);
+        if let Some((count, mut keys)) = self.vi.recording.take() {
+            keys.push(key);
+            self.vi.last_change = (count, keys);
+        }
+        if self.vi.undo.last().is_some_and(|b| b.line == buffer.line) {
+            self.vi.undo.pop();
+        }
+        Action::Edit
+    }
+
+    /// Runs a complete normal or visual mode command.
+    fn run(&mut self, buffer: &mut LineBuffer, count: Option<usize>, command: Command) -> Action {
+        let n = count.unwrap_or(1);
+        let visual = self.vi.mode == ViMode::Visual;
+
+        match command {
+            Command::Move(motion) => {
+                if let Some(target) = self.target(buffer, motion, n) {
+                    buffer.cursor = target;
+                }
+            },
+            Command::Operate(operator, motion) => self.operate(buffer, operator, motion, n),
+            Command::Insert(c) => {
+                buffer.cursor = match c {
+                    'a' => buffer.next(buffer.cursor),
+                    'I' => first_non_blank(&buffer.line),
+                    'A' => buffer.line.len(),
+                    _ => buffer.cursor,
+                };
+                self.vi.mode = ViMode::Insert;
+            },
+            Command::Replace(c) => {
+                let range = match self.selection(buffer) {
+                    Some(range) => range,
+                    None => {
+                        let end = (0..n).try_fold(buffer.cursor, |pos, _| (pos < buffer.line.len()).then(|| buffer.next(pos)));
+                        match end {
+                            Some(end) => buffer.cursor..end,
+                            None => return Action::Edit,
+                        }
+                    },
+                };
+                let replaced: String = buffer.line[range.clone()].chars().map(|_| c).collect();
+                buffer.line.replace_range(range.clone(), &replaced);
+                buffer.cursor = if visual { range.start } else { buffer.previous(range.start + replaced.len()) };
+                self.vi.mode = ViMode::Normal;
+            },
+            Command::ToggleCase => {
+                let range = self.selection(buffer)
+                    .unwrap_or_else(|| buffer.cursor..(0..n).fold(buffer.cursor, |pos, _| buffer.next(pos)));
+                let toggled: String = buffer.line[range.clone()].chars().map(toggle_case).collect();
+                buffer.line.replace_range(range.clone(), &toggled);
+                buffer.cursor = if visual { range.start } else { range.start + toggled.len() };
+                self.vi.mode = ViMode::Normal;
+            },
+            Command::Put(before) => {
+                let Some(text) = self.kill_ring.entries.front().map(|t| t.repeat(n)) else {
+                    return Action::Edit;
+                };
+                if let Some(range) = self.selection(buffer) {
+                    let replaced = buffer.remove(range);
+                    self.kill_ring.push(replaced);
+                } else if !before {
+                    buffer.cursor = buffer.next(buffer.cursor);
+                }
+                buffer.insert(&text);
+                buffer.cursor = buffer.previous(buffer.cursor);
+                self.vi.mode = ViMode::Normal;
+            },
+            Command::Undo => {
+                for _ in 0..n {
+                    if let Some(previous) = self.vi.undo.pop() {
+                        self.vi.redo.push(std::mem::replace(buffer, previous));
+                    }
+                }
+            },
+            Command::Redo => {
+                for _ in 0..n {
+                    if let Some(next) = self.vi.redo.pop() {
+                        self.vi.undo.push(std::mem::replace(buffer, next));
+                    }
+                }
+            },
+            Command::Repeat => {
+                let (original, keys) = self.vi.last_change.clone();
+                let digits = count.or(original).map(|c| c.to_string()).unwrap_or_default();
+
+                self.vi.repeating = true;
+                for key in digits.chars().map(|c| KeyEvent::from(KeyCode::Char(c))).chain(keys) {
+                    if let Action::Insert(c) = self.vi(buffer, key) {
+                        buffer.insert(c.encode_utf8(&mut [0; 4]));
+                    }
+                }
+                self.vi.repeating = false;
+            },
+            Command::Visual if visual => self.vi.mode = ViMode::Normal,
+            Command::Visual => {
+                self.vi.anchor = buffer.cursor;
+                self.vi.mode = ViMode::Visual;
+            },
+            Command::SwapEnds => {
+                let anchor = std::mem::replace(&mut self.vi.anchor, buffer.cursor);
+                buffer.cursor = buffer.line.floor_char_boundary(anchor);
+            },
+            Command::Action(action) => return action,
+        }
+
+        Action::Edit
+    }
+
+    /// Applies `operator` to the text from the cursor to where `motion` moves it.
+    fn operate(&mut self, buffer: &mut LineBuffer, operator: Operator, motion: Motion, count: usize) {
+        let range = match motion {
+            Motion::Line => 0..buffer.line.len(),
+            Motion::Selection => match self.selection(buffer) {
+                Some(range) => range,
+                None => return,
+            },
+            // Like vim, `cw` on a word changes up to its end rather than the next word
+            Motion::WordForward(big) if operator == Operator::Change && buffer.line[buffer.cursor..].starts_with(|c: char| !c.is_whitespace()) => {
+                let class = self.classifier(big);
+                let start = (1..count).fold(buffer.cursor, |pos, _| word_forward(&buffer.line, pos, &class));
+                buffer.cursor..run_end(&buffer.line, start, &class)
+            },
+            _ => match self.target(buffer, motion, count) {
+                Some(target) if target < buffer.cursor => target..buffer.cursor,
+                Some(target) if self.inclusive(motion) => buffer.cursor..buffer.next(target),
+                Some(target) => buffer.cursor..target,
+                None => return,
+            },
+        };
+
+        let text = buffer.line[range.clone()].to_string();
+        match operator {
+            Operator::Yank if motion == Motion::Line => (),
+            Operator::Yank => buffer.cursor = range.start,
+            Operator::Delete | Operator::Change => _ = buffer.remove(range),
+        }
+        self.kill_ring.push(text);
+        self.vi.mode = if operator == Operator::Change { ViMode::Insert } else { ViMode::Normal };
+    }
+
+    /// Where `motion` moves the cursor, if it can.
+    fn target(&mut self, buffer: &LineBuffer, motion: Motion, count: usize) -> Option<usize> {
+        let line = &buffer.line;
+        let repeat = |step: &dyn Fn(usize) -> usize| (0..count).fold(buffer.cursor, |pos, _| step(pos));
+
+        match motion {
+            Motion::Left => Some(repeat(&|pos| buffer.previous(pos))),
+            Motion::Right => Some(repeat(&|pos| buffer.next(pos))),
+            Motion::WordForward(big) => {
+                let class = self.classifier(big);
+                Some(repeat(&|pos| word_forward(line, pos, &class)))
+            },
+            Motion::WordBackward(big) => {
+                let class = self.classifier(big);
+                Some(repeat(&|pos| word_backward(line, pos, &class)))
+            },
+            Motion::WordEnd(big) => {
+                let class = self.classifier(big);
+                Some(repeat(&|pos| word_end(buffer, pos, &class)))
+            },
+            Motion::Start => Some(0),
+            Motion::FirstNonBlank => Some(first_non_blank(line)),
+            Motion::End => Some(line.len()),
+            Motion::Find(c, forward, till) => {
+                self.vi.last_find = Some((c, forward, till));
+                find(buffer, c, forward, till, count)
+            },
+            Motion::RepeatFind(reverse) => {
+                let (c, forward, till) = self.vi.last_find?;
+                find(buffer, c, forward != reverse, till, count)
+            },
+            Motion::Line | Motion::Selection => None,
+        }
+    }
+
+    /// Whether an operator with `motion` includes the character it moves to.
+    fn inclusive(&self, motion: Motion) -> bool {
+        match motion {
+            Motion::WordEnd(_) | Motion::Find(_, true, _) => true,
+            Motion::RepeatFind(reverse) => self.vi.last_find.is_some_and(|(_, forward, _)| forward != reverse),
+            _ => false,
+        }
+    }
+
+    /// Sorts characters into blanks (0), words (1) and other characters (2), or
+    /// only blanks and the rest for `big` words like vi's `W`.
+    fn classifier(&self, big: bool) -> impl Fn(char) -> u8 {
+        let word_chars = self.word_chars.clone();
+        move |c| if c.is_whitespace() {
+            0
+        } else if big || c.is_alphanumeric() || word_chars.contains(c) {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Swaps the characters before and at the cursor, or the two before it at the
+/// end of the line, moving the cursor past them.
+fn transpose(buffer: &mut LineBuffer) {
+    let pos = if buffer.cursor == buffer.line.len() { buffer.previous(buffer.cursor) } else { buffer.cursor };
+    let start = buffer.previous(pos);
+    let end = buffer.next(pos);
+    if start == pos || pos == end {
+        return;
+    }
+
+    let swapped = format!("{}{}", &buffer.line[pos..end], &buffer.line[start..pos]);
+    buffer.line.replace_range(start..end, &swapped);
+    buffer.cursor = end;
+}
+
+/// `word` with its first word character in upper case and the rest in lower case.
+fn capitalize(word: &str, is_word: &dyn Fn(char) -> bool) -> String {
+    let start = word.len() - word.trim_start_matches(|c| !is_word(c)).len();
+    let mut chars = word[start..].chars();
+    let first = chars.next().map(|c| c.to_uppercase().to_string()).unwrap_or_default();
+
+    format!("{}{}{}", &word[..start], first, chars.as_str().to_lowercase())
+}
+
+/// Parses the keys of a normal or visual mode command into its count and the
+/// command, `None` if it needs more keys.
+fn parse_command(keys: &[char], mode: ViMode) -> Result<Option<(Option<usize>, Command)>, ()> {
+    let mut keys = keys.iter().copied();
+    let (mut count, Some(c)) = parse_count(&mut keys) else {
+        return Ok(None);
+    };
+    let visual = mode == ViMode::Visual;
+
+    let command = match c {
+        'd' | 'x' if visual => Command::Operate(Operator::Delete, Motion::Selection),
+        'c' | 's' if visual => Command::Operate(Operator::Change, Motion::Selection),
+        'y' if visual => Command::Operate(Operator::Yank, Motion::Selection),
+        'd' | 'c' | 'y' => {
+            let operator = match c {
+                'd' => Operator::Delete,
+                'c' => Operator::Change,
+                _ => Operator::Yank,
+            };
+            let (inner, Some(m)) = parse_count(&mut keys) else {
+                return Ok(None);
+            };
+            // Counts before the operator and the motion multiply, as in `2d3w`
+            if count.is_some() || inner.is_some() {
+                count = Some((count.unwrap_or(1) * inner.unwrap_or(1)).min(MAX_COUNT));
+            }
+            let motion = if m == c {
+                Motion::Line
+            } else {
+                match parse_motion(m, &mut keys)? {
+                    Some(motion) => motion,
+                    None => return Ok(None),
+                }
+            };
+            Command::Operate(operator, motion)
+        },
+        'x' => Command::Operate(Operator::Delete, Motion::Right),
+        'X' => Command::Operate(Operator::Delete, Motion::Left),
+        's' => Command::Operate(Operator::Change, Motion::Right),
+        'S' => Command::Operate(Operator::Change, Motion::Line),
+        'D' => Command::Operate(Operator::Delete, Motion::End),
+        'C' => Command::Operate(Operator::Change, Motion::End),
+        'Y' => Command::Operate(Operator::Yank, Motion::Line),
+        'i' | 'a' | 'I' | 'A' if !visual => Command::Insert(c),
+        'r' => match keys.next() {
+            Some(c) => Command::Replace(c),
+            None => return Ok(None),
+        },
+        '~' => Command::ToggleCase,
+        'p' | 'P' => Command::Put(c == 'P'),
+        'u' => Command::Undo,
+        '.' if !visual => Command::Repeat,
+        'v' => Command::Visual,
+        'o' if visual => Command::SwapEnds,
+        'j' => Command::Action(Action::HistoryNext),
+        'k' => Command::Action(Action::HistoryPrevious),
+        _ => match parse_motion(c, &mut keys)? {
+            Some(motion) => Command::Move(motion),
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some((count, command)))
+}
+
+/// Reads a count, which can't start with 0 as that moves to the start of the
+/// line, and the key after it.
+fn parse_count(keys: &mut impl Iterator<Item = char>) -> (Option<usize>, Option<char>) {
+    let mut count: Option<usize> = None;
+
+    for c in keys.by_ref() {
+        match c.to_digit(10) {
+            Some(d) if d > 0 || count.is_some() => count = Some((count.unwrap_or(0) * 10 + d as usize).min(MAX_COUNT)),
+            _ => return (count, Some(c)),
+        }
+    }
+    (count, None)
+}
+
+fn parse_motion(c: char, keys: &mut impl Iterator<Item = char>) -> Result<Option<Motion>, ()> {
+    Ok(Some(match c {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' | 'W' => Motion::WordForward(c == 'W'),
+        'b' | 'B' => Motion::WordBackward(c == 'B'),
+        'e' | 'E' => Motion::WordEnd(c == 'E'),
+        '0' => Motion::Start,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::End,
+        'f' | 'F' | 't' | 'T' => match keys.next() {
+            Some(target) => Motion::Find(target, c.is_lowercase(), c.eq_ignore_ascii_case(&'t')),
+            None => return Ok(None),
+        },
+        ';' => Motion::RepeatFind(false),
+        ',' => Motion::RepeatFind(true),
+        _ => return Err(()),
+    }))
+}
+
+/// The position of the `count`th `c` after the cursor, or before it unless
+/// searching `forward`. With `till`, the position next to it on the cursor's side.
+fn find(buffer: &LineBuffer, c: char, forward: bool, till: bool, count: usize) -> Option<usize> {
+    let found = if forward {
+        let start = buffer.next(buffer.cursor);
+        start + buffer.line[start..].match_indices(c).nth(count - 1)?.0
+    } else {
+        buffer.line[..buffer.cursor].rmatch_indices(c).nth(count - 1)?.0
+    };
+
+    Some(match (till, forward) {
+        (false, _) => found,
+        (true, true) => buffer.previous(found),
+        (true, false) => buffer.next(found),
+    })
+}
+
+/// The end of the characters of the same class as the one at `pos`, which is
+/// `pos` itself for blanks.
+fn run_end(line: &str, pos: usize, class: &dyn Fn(char) -> u8) -> usize {
+    let first = line[pos..].chars().next().map_or(0, class);
+    line.len() - line[pos..].trim_start_matches(|c| first != 0 && class(c) == first).len()
+}
+
+/// The start of the next word after `pos`.
+fn word_forward(line: &str, pos: usize, class: &dyn Fn(char) -> u8) -> usize {
+    line.len() - line[run_end(line, pos, class)..].trim_start().len()
+}
+
+/// The start of the word before `pos`.
+fn word_backward(line: &str, pos: usize, class: &dyn Fn(char) -> u8) -> usize {
+    let before = line[..pos].trim_end();
+    let last = before.chars().next_back().map_or(0, class);
+    before.trim_end_matches(|c| class(c) == last).len()
+}
+
+/// The last character of the word after `pos`.
+fn word_end(buffer: &LineBuffer, pos: usize, class: &dyn Fn(char) -> u8) -> usize {
+    let start = buffer.next(pos);
+    let start = buffer.line.len() - buffer.line[start..].trim_start().len();
+    buffer.previous(run_end(&buffer.line, start, class)).max(pos)
+}
+
+fn first_non_blank(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn toggle_case(c: char) -> String {
+    if c.is_lowercase() {
+        c.to_uppercase().to_string()
+    } else {
+        c.to_lowercase().to_string()
+    }
+}
+
+
+#[test]
+fn test_emacs() {
+    let mut editor = Editor::new();
+    editor.word_chars = DEFAULT_WORD_CHARS.to_string();
+    let mut buffer = LineBuffer::new("git commit -m fix_typo");
+    let mut press = |buffer: &mut LineBuffer, code, modifiers| editor.emacs(buffer, KeyEvent::new(code, modifiers));
+
+    press(&mut buffer, KeyCode::Char('b'), KeyModifiers::ALT);
+    assert_eq!(buffer.cursor, 14);
+    press(&mut buffer, KeyCode::Char('w'), KeyModifiers::CONTROL);
+    assert_eq!(buffer.line, "git commit fix_typo");
+    press(&mut buffer, KeyCode::Char('w'), KeyModifiers::CONTROL);
+    press(&mut buffer, KeyCode::Char('e'), KeyModifiers::CONTROL);
+    press(&mut buffer, KeyCode::Backspace, KeyModifiers::ALT);
+    assert_eq!(buffer.line, "git ");
+    press(&mut buffer, KeyCode::Char('y'), KeyModifiers::CONTROL);
+    press(&mut buffer, KeyCode::Char('y'), KeyModifiers::ALT);
+    assert_eq!(buffer.line, "git commit -m ");
+
+    press(&mut buffer, KeyCode::Char('a'), KeyModifiers::CONTROL);
+    press(&mut buffer, KeyCode::Char('c'), KeyModifiers::ALT);
+    assert_eq!((buffer.line.as_str(), buffer.cursor), ("Git commit -m ", 3));
+    press(&mut buffer, KeyCode::Char('k'), KeyModifiers::CONTROL);
+    press(&mut buffer, KeyCode::Char('t'), KeyModifiers::CONTROL);
+    assert_eq!(buffer.line, "Gti");
+    assert_eq!(press(&mut buffer, KeyCode::Char('u'), KeyModifiers::CONTROL), Action::Edit);
+    assert_eq!(press(&mut buffer, KeyCode::Char('d'), KeyModifiers::CONTROL), Action::Eof);
+}
+
+#[test]
+fn test_vi() {
+    let mut editor = Editor::new();
+    editor.edit_mode = EditMode::Vi;
+    editor.word_chars = DEFAULT_WORD_CHARS.to_string();
+    let mut buffer = LineBuffer::new("git commit -m fix_typo");
+    editor.start_line(&buffer);
+    let mut typing = |buffer: &mut LineBuffer, keys: &str| for c in keys.chars() {
+        let code = if c == '\x1b' { KeyCode::Esc } else { KeyCode::Char(c) };
+        if let Action::Insert(c) = editor.handle(buffer, KeyEvent::from(code)) {
+            buffer.insert(c.encode_utf8(&mut [0; 4]));
+        }
+    };
+
+    typing(&mut buffer, "\x1b0w");
+    assert_eq!(buffer.cursor, 4);
+    typing(&mut buffer, "cwswitch\x1b$b.");
+    assert_eq!(buffer.line, "git switch -m switch");
+    typing(&mut buffer, "uu3dw");
+    assert_eq!((buffer.line.as_str(), buffer.cursor), ("git fix_typo", 4));
+    typing(&mut buffer, "$F_dT ");
+    assert_eq!(buffer.line, "git _typo");
+    typing(&mut buffer, "0ve~");
+    assert_eq!(buffer.line, "GIT _typo");
+    typing(&mut buffer, "wvlly$p");
+    assert_eq!(buffer.line, "GIT _typo_ty");
+    typing(&mut buffer, "0dtpA!\x1b");
+    assert_eq!((buffer.line.as_str(), buffer.cursor), ("po_ty!", 5));
+    typing(&mut buffer, "0x3.");
+    assert_eq!(buffer.line, "y!");
+    assert_eq!(editor.mode_name(), "normal");
+
+    // A prompt showing the mode precedes only the next line
+    editor.indicators = [DEFAULT_INSERT_INDICATOR, DEFAULT_NORMAL_INDICATOR, DEFAULT_VISUAL_INDICATOR].map(String::from);
+    editor.mode_prompt = Some("{mode}$ ".to_string());
+    editor.start_line(&buffer);
+    assert_eq!((editor.mode_name(), editor.line_prefix().as_str()), ("insert", "[I] $ "));
+    editor.start_line(&buffer);
+    assert_eq!(editor.line_prefix(), "[I] ");
+}
@@ -1,13 +1,14 @@
 #![allow(unused, dead_code, unused_unsafe)]
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Write,
     os::fd::{RawFd, AsRawFd},
 };
 
 use rlua::{
     prelude,
+    FromLua,
     ToLua
 };
 
@@ -20,6 +21,11 @@ use crate::{
     log::*,
     parser,
     config,
+    frecency,
+    traps,
+    termio,
+    coproc::Coprocess,
+    timing::JobTimes,
     tag,
 };
 
@@ -32,6 +38,17 @@ extern "C" {
 }
 
 const SCRIPTS_DIR: &str = "${HOME}/.luabster/scripts";
+const HOOKS_REGISTRY_KEY: &str = "luabster_hooks";
+const ALIASES_REGISTRY_KEY: &str = "luabster_aliases";
+const TRAPS_REGISTRY_KEY: &str = "luabster_traps";
+const CLI_PARSER_REGISTRY_KEY: &str = "luabster_cli_parser";
+const HOOK_EVENTS: [&str; 5] = [
+    "preexec",
+    "precmd",
+    "chpwd",
+    "exit",
+    "command_not_found",
+];
 
 impl<'a> config::Configurable<'a> for LuaScripts {
     
@@ -51,6 +68,15 @@ impl<'a> config::Configurable<'a> for LuaScripts {
     }
 }
 
+/// What a hook asked the shell to do instead of its default behaviour.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookReply {
+    /// The hook took care of the event.
+    Handled,
+    /// Run this command line in place of the original.
+    Replace(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct LuaScripts {
     dir: String
@@ -61,6 +87,8 @@ pub struct LuaScripts {
 pub struct LuaParser {
     vars: Vec<(String, bool)>,
     var_dir: String,
+    /// The globals defined before any user code ran, e.g. the standard library.
+    builtin_globals: HashSet<String>,
     pub lua: rlua::Lua,
     pub scripts: LuaScripts,
 }
@@ -80,6 +108,7 @@ impl LuaParser {
         let mut this = Self {
             vars: Vec::new(),
             var_dir: home_dir.to_owned(),
+            builtin_globals: HashSet::new(),
             lua: rlua::Lua::new(),
             scripts: LuaScripts { dir: SCRIPTS_DIR.to_string() },
         };
@@ -103,9 +132,404 @@ impl LuaParser {
             Ok(())
         });
 
+        this.register_api();
+
+        let _: Result<(), rlua::Error> = this.lua.context(|lua_ctx| {
+            for pair in lua_ctx.globals().pairs::<String, rlua::Value>() {
+                this.builtin_globals.insert(pair?.0);
+            }
+            Ok(())
+        });
+
         this
     }
 
+    /// Sets the shell that the `luabster` Lua API executes commands in.
+    pub fn set_cli_parser(&mut self, cli_parser: *mut std::ffi::c_void) {
+        let res: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
+            lua_ctx.set_named_registry_value(CLI_PARSER_REGISTRY_KEY, rlua::LightUserData(cli_parser))
+        });
+
+        log!(LogLevel::Debug, "Set the Lua API's shell: {:?}", res);
+    }
+
+    fn register_api(&mut self) {
+        let res: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
+            let api = lua_ctx.create_table()?;
+
+            let hooks = lua_ctx.create_table()?;
+            for event in HOOK_EVENTS {
+                hooks.set(event, lua_ctx.create_table()?)?;
+            }
+            lua_ctx.set_named_registry_value(HOOKS_REGISTRY_KEY, hooks)?;
+
+            api.set("on", lua_ctx.create_function(|lua_ctx, (event, hook): (String, rlua::Function)| {
+                let hooks: rlua::Table = lua_ctx.named_registry_value(HOOKS_REGISTRY_KEY)?;
+                match hooks.get::<_, Option<rlua::Table>>(event.as_str())? {
+                    Some(event_hooks) => event_hooks.set(event_hooks.len()? + 1, hook),
+                    None => Err(rlua::Error::RuntimeError(format!("unknown event `{}`", event)))
+                }
+            })?)?;
+
+            api.set("execute", lua_ctx.create_function(|lua_ctx, cmd: String| {
+                let status = with_cli_parser(lua_ctx, |p| {
+                    _ = p.parse_inputs(&cmd);
+                    p.last_status()
+                })?;
+                Ok(status.unwrap_or(127))
+            })?)?;
+
+            lua_ctx.set_named_registry_value(ALIASES_REGISTRY_KEY, lua_ctx.create_table()?)?;
+
+            // A function alias receives the argument list and returns the command to run
+            api.set("alias", lua_ctx.create_function(|lua_ctx, (name, body): (String, rlua::Value)| {
+                let lua_aliases: rlua::Table = lua_ctx.named_registry_value(ALIASES_REGISTRY_KEY)?;
+                match body {
+                    rlua::Value::Function(f) => {
+                        lua_aliases.set(name, f)?;
+                    },
+                    rlua::Value::Nil => {
+                        lua_aliases.set(name.as_str(), rlua::Value::Nil)?;
+                        with_cli_parser(lua_ctx, |p| p.remove_alias(&name))?;
+                    },
+                    body => {
+                        let body = String::from_lua(body, lua_ctx)?;
+                        with_cli_parser(lua_ctx, |p| p.set_alias(&name, &body))?;
+                    }
+                }
+                Ok(())
+            })?)?;
+
+            api.set("abbr", lua_ctx.create_function(|lua_ctx, (name, expansion): (String, Option<String>)| {
+                with_cli_parser(lua_ctx, |p| match expansion {
+                    Some(expansion) => p.input_parser.set_abbreviation(&name, &expansion),
+                    None => _ = p.input_parser.remove_abbreviation(&name),
+                })?;
+                Ok(())
+            })?)?;
+
+            // Proxy table reading and writing the shell's aliases
+            let aliases = lua_ctx.create_table()?;
+            let aliases_meta = lua_ctx.create_table()?;
+            aliases_meta.set("__index", lua_ctx.create_function(|lua_ctx, (_, name): (rlua::Table, String)| {
+                Ok(with_cli_parser(lua_ctx, |p| p.get_alias(&name))?.flatten())
+            })?)?;
+            aliases_meta.set("__newindex", lua_ctx.create_function(|lua_ctx, (_, name, body): (rlua::Table, String, Option<String>)| {
+                with_cli_parser(lua_ctx, |p| match body {
+                    Some(body) => p.set_alias(&name, &body),
+                    None => _ = p.remove_alias(&name),
+                })?;
+                Ok(())
+            })?)?;
+            aliases_meta.set("__pairs", lua_ctx.create_function(|lua_ctx, _: rlua::Table| {
+                let snapshot = lua_ctx.create_table()?;
+                for (name, body) in with_cli_parser(lua_ctx, |p| p.get_aliases())?.unwrap_or_default() {
+                    snapshot.set(name, body)?;
+                }
+                let next: rlua::Function = lua_ctx.globals().get("next")?;
+                Ok((next, snapshot, rlua::Value::Nil))
+            })?)?;
+            aliases.set_metatable(Some(aliases_meta));
+            api.set("aliases", aliases)?;
+
+            lua_ctx.set_named_registry_value(TRAPS_REGISTRY_KEY, lua_ctx.create_table()?)?;
+
+            // Like `trap`, but the handler can also be a function receiving the signal name
+            api.set("trap", lua_ctx.create_function(|lua_ctx, (condition, handler): (String, rlua::Value)| {
+                let Some(name) = traps::condition_name(&condition) else {
+                    return Err(rlua::Error::RuntimeError(format!("unknown signal `{}`", condition)));
+                };
+                match handler {
+                    rlua::Value::Function(f) => {
+                        with_cli_parser(lua_ctx, |p| p.set_trap(&name, None))?;
+                        let lua_traps: rlua::Table = lua_ctx.named_registry_value(TRAPS_REGISTRY_KEY)?;
+                        lua_traps.set(name.as_str(), f)?;
+                        traps::catch(&name);
+                    },
+                    rlua::Value::Nil => {
+                        with_cli_parser(lua_ctx, |p| p.set_trap(&name, None))?;
+                    },
+                    handler => {
+                        let handler = String::from_lua(handler, lua_ctx)?;
+                        with_cli_parser(lua_ctx, |p| p.set_trap(&name, Some(&handler)))?;
+                    }
+                }
+                Ok(())
+            })?)?;
+
+            // Proxy table reading and writing the `set` options
+            let options = lua_ctx.create_table()?;
+            let options_meta = lua_ctx.create_table()?;
+            options_meta.set("__index", lua_ctx.create_function(|lua_ctx, (_, name): (rlua::Table, String)| {
+                Ok(with_cli_parser(lua_ctx, |p| p.get_option(&name))?.flatten())
+            })?)?;
+            options_meta.set("__newindex", lua_ctx.create_function(|lua_ctx, (_, name, value): (rlua::Table, String, bool)| {
+                match with_cli_parser(lua_ctx, |p| p.set_option(&name, value))? {
+                    Some(false) => Err(rlua::Error::RuntimeError(format!("unknown option `{}`", name))),
+                    _ => Ok(())
+                }
+            })?)?;
+            options_meta.set("__pairs", lua_ctx.create_function(|lua_ctx, _: rlua::Table| {
+                let snapshot = lua_ctx.create_table()?;
+                let values = with_cli_parser(lua_ctx, |p| {
+                    crate::options::OPTION_NAMES.iter().map(|(name, _)| (*name, p.get_option(name))).collect::<Vec<_>>()
+                })?;
+                for (name, value) in values.unwrap_or_default() {
+                    snapshot.set(name, value)?;
+                }
+                let next: rlua::Function = lua_ctx.globals().get("next")?;
+                Ok((next, snapshot, rlua::Value::Nil))
+            })?)?;
+            options.set_metatable(Some(options_meta));
+            api.set("options", options)?;
+
+            // Frecency database of visited directories, as used by `z`
+            let dirs = lua_ctx.create_table()?;
+            dirs.set("query", lua_ctx.create_function(|lua_ctx, fragments: rlua::Variadic<String>| {
+                let matches = lua_ctx.create_table()?;
+                for (i, entry) in frecency::query(&fragments).iter().enumerate() {
+                    let m = lua_ctx.create_table()?;
+                    m.set("path", entry.path.as_str())?;
+                    m.set("rank", entry.rank)?;
+                    m.set("last_visit", entry.last_visit)?;
+                    m.set("score", frecency::score(entry))?;
+                    matches.set(i + 1, m)?;
+                }
+                Ok(matches)
+            })?)?;
+            dirs.set("add", lua_ctx.create_function(|_, dir: String| {
+                frecency::add(&dir);
+                Ok(())
+            })?)?;
+            dirs.set("remove", lua_ctx.create_function(|_, dir: String| {
+                Ok(frecency::remove(&dir))
+            })?)?;
+            api.set("dirs", dirs)?;
+
+            // Starts a command connected to the script, e.g. `luabster.spawn{"bc", "-q"}`
+            api.set("spawn", lua_ctx.create_function(|lua_ctx, args: Vec<String>| {
+                if args.is_empty() {
+                    return Err(rlua::Error::RuntimeError("spawn: command expected".to_string()));
+                }
+                let command = args.iter().map(|a| parser::quote_word(a)).join(" ");
+                with_cli_parser(lua_ctx, |p| p.spawn_coprocess(&command))
+            })?)?;
+
+            // Interactive picker, e.g. `luabster.choose({"a", "b"}, {multi = true, fuzzy = true})`,
+            // returning the chosen item, a list of them with `multi`, or nil if cancelled
+            api.set("choose", lua_ctx.create_function(|lua_ctx, (items, opts): (Vec<String>, Option<rlua::Table>)| {
+                let flag = |name: &str| -> rlua::Result<bool> {
+                    Ok(opts.as_ref().map(|o| o.get::<_, Option<bool>>(name)).transpose()?.flatten().unwrap_or(false))
+                };
+                let multi = flag("multi")?;
+                let options: Vec<&str> = items.iter().map(String::as_str).collect();
+
+                let chosen = termio::choose(&options, multi, flag("fuzzy")?)
+                    .map_err(|e| rlua::Error::RuntimeError(format!("choose: {}", e)))?;
+                match chosen {
+                    None => Ok(rlua::Value::Nil),
+                    Some(chosen) if multi => chosen.iter().map(|&i| options[i]).collect::<Vec<_>>().to_lua(lua_ctx),
+                    Some(chosen) => options[chosen[0]].to_lua(lua_ctx),
+                }
+            })?)?;
+
+            // Prompts for a line, e.g. `luabster.input{prompt = "Password: ", hidden = true}`,
+            // returning nil if cancelled
+            api.set("input", lua_ctx.create_function(|_, opts: Option<rlua::Table>| {
+                let prompt: Option<String> = opts.as_ref().map(|o| o.get("prompt")).transpose()?.flatten();
+                let default: Option<String> = opts.as_ref().map(|o| o.get("default")).transpose()?.flatten();
+                let hidden: Option<bool> = opts.as_ref().map(|o| o.get("hidden")).transpose()?.flatten();
+
+                answer_or_nil(termio::prompt_for_input(&prompt.unwrap_or_default(), default.as_deref(), hidden.unwrap_or(false)))
+            })?)?;
+
+            // Asks a yes/no question, e.g. `luabster.confirm("Deploy? [y/N] ", false)`
+            api.set("confirm", lua_ctx.create_function(|_, (prompt, default): (String, Option<bool>)| {
+                answer_or_nil(termio::confirm(&prompt, default.unwrap_or(false)))
+            })?)?;
+
+            // Asks for a number until a valid one is given, e.g. `luabster.number{prompt = "Port: ", min = 1, max = 65535}`
+            api.set("number", lua_ctx.create_function(|lua_ctx, opts: Option<rlua::Table>| {
+                let field = |name: &str| -> rlua::Result<Option<f64>> {
+                    Ok(opts.as_ref().map(|o| o.get(name)).transpose()?.flatten())
+                };
+                let prompt: Option<String> = opts.as_ref().map(|o| o.get("prompt")).transpose()?.flatten();
+
+                let number = answer_or_nil(termio::prompt_for_number(&prompt.unwrap_or_default(), field("default")?, field("min")?, field("max")?))?;
+                // Whole numbers are integers, so that e.g. a port isn't printed as `8080.0`
+                match number {
+                    Some(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(rlua::Value::Integer(n as i64)),
+                    n => n.to_lua(lua_ctx),
+                }
+            })?)?;
+
+            // The match and groups of the last `=~`, indexed like `BASH_REMATCH` from 0
+            api.set("rematch", lua_ctx.create_function(|lua_ctx, ()| {
+                let groups = lua_ctx.create_table()?;
+                for (i, group) in with_cli_parser(lua_ctx, |p| p.rematch().to_vec())?.unwrap_or_default().into_iter().enumerate() {
+                    groups.set(i, group)?;
+                }
+                Ok(groups)
+            })?)?;
+
+            // The line editor's mode, `emacs` or vi's `insert`, `normal` or `visual`
+            api.set("edit_mode", lua_ctx.create_function(|_, ()| Ok(termio::editor().mode_name()))?)?;
+
+            lua_ctx.globals().set("luabster", api)?;
+
+            Ok(())
+        });
+
+        log!(LogLevel::Debug, "Registered Lua API: {:?}", res);
+    }
+
+    /// Calls every hook registered for `event` with `args`. A failing hook is reported
+    /// but does not stop the remaining hooks from running.
+    pub fn run_hooks<A>(&self, event: &str, args: A) where A: for<'lua> rlua::ToLuaMulti<'lua> + Clone {
+        let res: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
+            let hooks: rlua::Table = lua_ctx.named_registry_value(HOOKS_REGISTRY_KEY)?;
+            let event_hooks: rlua::Table = hooks.get(event)?;
+
+            for hook in event_hooks.sequence_values::<rlua::Function>() {
+                if let Err(e) = hook?.call::<_, ()>(args.clone()) {
+                    eprintln!("luabster: {} hook failed: {}", event, e);
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Err(e) = res {
+            log!(LogLevel::Error, "Unable to run {} hooks: {:?}", event, e);
+        }
+    }
+
+    /// Calls the hooks registered for `event` until one of them returns `true`
+    /// (handled) or a string (replacement command line).
+    pub fn run_reply_hooks<A>(&self, event: &str, args: A) -> Option<HookReply> where A: for<'lua> rlua::ToLuaMulti<'lua> + Clone {
+        let res: Result<Option<HookReply>, rlua::Error> = self.lua.context(|lua_ctx| {
+            let hooks: rlua::Table = lua_ctx.named_registry_value(HOOKS_REGISTRY_KEY)?;
+            let event_hooks: rlua::Table = hooks.get(event)?;
+
+            for hook in event_hooks.sequence_values::<rlua::Function>() {
+                match hook?.call::<_, rlua::Value>(args.clone()) {
+                    Ok(rlua::Value::Boolean(true)) => return Ok(Some(HookReply::Handled)),
+                    Ok(rlua::Value::String(s)) => return Ok(Some(HookReply::Replace(s.to_str()?.to_string()))),
+                    Ok(_) => (),
+                    Err(e) => eprintln!("luabster: {} hook failed: {}", event, e),
+                }
+            }
+
+            Ok(None)
+        });
+
+        match res {
+            Ok(reply) => reply,
+            Err(e) => {
+                log!(LogLevel::Error, "Unable to run {} hooks: {:?}", event, e);
+                None
+            }
+        }
+    }
+
+    /// Calls the Lua function alias `name` with `args`, returning the command it expands to.
+    pub fn call_alias(&self, name: &str, args: &[String]) -> Option<String> {
+        let res: Result<Option<String>, rlua::Error> = self.lua.context(|lua_ctx| {
+            let lua_aliases: rlua::Table = lua_ctx.named_registry_value(ALIASES_REGISTRY_KEY)?;
+            match lua_aliases.get::<_, Option<rlua::Function>>(name)? {
+                Some(f) => f.call(args.to_vec()),
+                None => Ok(None)
+            }
+        });
+
+        match res {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("luabster: alias {} failed: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Calls the Lua trap handler for `name`, returning false if there is none.
+    pub fn run_trap(&self, name: &str) -> bool {
+        let res: Result<bool, rlua::Error> = self.lua.context(|lua_ctx| {
+            let lua_traps: rlua::Table = lua_ctx.named_registry_value(TRAPS_REGISTRY_KEY)?;
+            match lua_traps.get::<_, Option<rlua::Function>>(name)? {
+                Some(f) => f.call::<_, ()>(name).map(|_| true),
+                None => Ok(false)
+            }
+        });
+
+        match res {
+            Ok(ran) => ran,
+            Err(e) => {
+                eprintln!("luabster: {} trap failed: {}", name, e);
+                true
+            }
+        }
+    }
+
+    pub fn remove_trap(&self, name: &str) {
+        _ = self.lua.context(|lua_ctx| -> Result<(), rlua::Error> {
+            let lua_traps: rlua::Table = lua_ctx.named_registry_value(TRAPS_REGISTRY_KEY)?;
+            lua_traps.set(name, rlua::Value::Nil)
+        });
+    }
+
+    /// The conditions that have a Lua function as trap handler.
+    pub fn get_traps(&self) -> Vec<String> {
+        self.lua.context(|lua_ctx| -> Result<Vec<String>, rlua::Error> {
+            let lua_traps: rlua::Table = lua_ctx.named_registry_value(TRAPS_REGISTRY_KEY)?;
+            lua_traps.pairs::<String, rlua::Function>().map(|pair| pair.map(|(name, _)| name)).collect()
+        }).unwrap_or_default()
+    }
+
+    pub fn is_function(&self, name: &str) -> bool {
+        self.lua.context(|lua_ctx| {
+            matches!(lua_ctx.globals().get::<_, rlua::Value>(name), Ok(rlua::Value::Function(_)))
+        })
+    }
+
+    /// Runs `path` as a standalone Lua script, returning its exit code.
+    pub fn run_script(&self, path: &str, args: &[String]) -> i32 {
+        let code = match std::fs::read_to_string(path) {
+            Ok(code) => strip_shebang(code),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return 1;
+            }
+        };
+
+        let res: Result<i32, rlua::Error> = self.lua.context(|lua_ctx| {
+            let arg = lua_ctx.create_table()?;
+            arg.set(0, path)?;
+            for (i, a) in args.iter().enumerate() {
+                arg.set(i + 1, a.as_str())?;
+            }
+            lua_ctx.globals().set("arg", arg)?;
+
+            let ret: rlua::Value = lua_ctx.load(&code)
+                .set_name(path)?
+                .call(args.iter().cloned().collect::<rlua::Variadic<String>>())?;
+
+            Ok(match ret {
+                rlua::Value::Boolean(b) => if b { 0 } else { 1 },
+                rlua::Value::Integer(n) => n as i32,
+                rlua::Value::Number(n) => n as i32,
+                _ => 0
+            })
+        });
+
+        match res {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+
     pub fn parse(&mut self, command: &str, first: bool, last: bool) -> Option<parser::Child> {
         let is_lua_command = command.starts_with(LUA_PREFIX);
 
@@ -121,33 +545,45 @@ impl LuaParser {
         None
     }
 
-    pub fn load_config<'a>(&self, params: &[&'a str], home_dir: &str) -> HashMap<&'a str, String> {
+    pub fn load_config<'a>(&self, params: &[&'a str], config_files: &[String]) -> HashMap<&'a str, String> {
         let mut map = HashMap::new();
-        let res: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
-            let globals = lua_ctx.globals();
-            _ = lua_ctx.load(&format!("LuabsterConfig = dofile \"{}/.luabster/config.lua\"", home_dir)).exec()?;
-            params.iter().for_each(|p| {
-                let conf = globals.get("LuabsterConfig");
-                if conf.is_err() { return }
-                let conf: rlua::Table = conf.unwrap();
-                let mut subtables = p.split(".").collect_vec();
-                let key = subtables.pop().unwrap();
-                if let Ok(subtable) = subtables.iter().try_fold(conf, |cur_table, subtable| cur_table.get(*subtable) ) {
-                    match subtable.get::<&str, String>(key) {
-                        Ok(s) => {
-                            map.insert(*p, s);
-                        },
-                        Err(_) => {
-                            log!(LogLevel::Debug, "Config param not found: {}", p);
-                        }
-                    };
-                }
-            });
 
-            Ok(())
-        });
+        // Later files override earlier ones
+        for config_file in config_files {
+            let res: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
+                let globals = lua_ctx.globals();
+                _ = lua_ctx.load(&format!("LuabsterConfig = dofile \"{}\"", config_file)).exec()?;
+                params.iter().for_each(|p| {
+                    let conf = globals.get("LuabsterConfig");
+                    if conf.is_err() { return }
+                    let conf: rlua::Table = conf.unwrap();
+                    let mut subtables = p.split(".").collect_vec();
+                    let key = subtables.pop().unwrap();
+                    if let Ok(subtable) = subtables.iter().try_fold(conf, |cur_table, subtable| cur_table.get(*subtable) ) {
+                        match subtable.get::<&str, rlua::Value>(key) {
+                            Ok(rlua::Value::Boolean(b)) => {
+                                map.insert(*p, b.to_string());
+                            },
+                            Ok(v) => match String::from_lua(v, lua_ctx) {
+                                Ok(s) => {
+                                    map.insert(*p, s);
+                                },
+                                Err(_) => {
+                                    log!(LogLevel::Debug, "Config param not found: {}", p);
+                                }
+                            },
+                            Err(_) => {
+                                log!(LogLevel::Debug, "Config param not found: {}", p);
+                            }
+                        };
+                    }
+                });
 
-        log!(LogLevel::Debug, "{:?}", res);
+                Ok(())
+            });
+
+            log!(LogLevel::Debug, "{}: {:?}", config_file, res);
+        }
         
         map
     }
@@ -155,20 +591,28 @@ impl LuaParser {
     fn run_command(&mut self, cmd: &str) {
     }
 
-    pub fn get_possible_correction(&self, token: &str) -> Option<String> {
-        let mut res: Option<String> = None;
-        //let _: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
-        //    let globals = lua_ctx.globals();
+    /// Returns the global functions, including those loaded from the scripts
+    /// directory, that are similar to `token` along with their similarity.
+    pub fn get_possible_corrections(&self, token: &str, threshold: f64) -> Vec<(String, f64)> {
+        let mut res = Vec::new();
+        let _: Result<(), rlua::Error> = self.lua.context(|lua_ctx| {
+            let globals = lua_ctx.globals();
 
-        //    for pair in globals.pairs::<rlua::Value, rlua::Value>() {
-        //        let (p, v) = pair?;
-        //        if strsim::jaro_winkler(p, token) > 0.92 {
-        //            res =  Some(p);
-        //            break;
-        //        }
-        //    }
-        //    Ok(())
-        //});
+            for pair in globals.pairs::<rlua::Value, rlua::Value>() {
+                if let (rlua::Value::String(p), rlua::Value::Function(_)) = pair? {
+                    let p = p.to_str()?;
+                    // Only functions the user defined, not e.g. `print`
+                    if self.builtin_globals.contains(p) {
+                        continue;
+                    }
+                    let score = strsim::jaro_winkler(p, token);
+                    if score > threshold && p != token {
+                        res.push((p.to_string(), score));
+                    }
+                }
+            }
+            Ok(())
+        });
 
         res
     }
@@ -282,6 +726,53 @@ pub extern "C" fn run_lua(l: *mut std::ffi::c_void, cmd: *const std::ffi::c_ucha
     }
 }
 
+/// Runs `f` on the shell set with `set_cli_parser`, if any. The shell may itself be
+/// running the calling Lua code, so the reference must not outlive the call.
+fn with_cli_parser<T>(lua_ctx: rlua::Context, f: impl FnOnce(&mut parser::CliParser) -> T) -> rlua::Result<Option<T>> {
+    let cli_parser: Option<rlua::LightUserData> = lua_ctx.named_registry_value(CLI_PARSER_REGISTRY_KEY)?;
+    Ok(cli_parser.map(|p| f(unsafe { &mut *(p.0 as *mut parser::CliParser) })))
+}
+
+/// The answer to a prompt, nil if it was cancelled or stdin ended.
+fn answer_or_nil<T>(answer: std::io::Result<T>) -> rlua::Result<Option<T>> {
+    match answer {
+        Ok(answer) => Ok(Some(answer)),
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::Interrupted | std::io::ErrorKind::UnexpectedEof) => Ok(None),
+        Err(e) => Err(rlua::Error::RuntimeError(e.to_string())),
+    }
+}
+
+/// Quotes `s` as a Lua string literal.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            c    => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Builds Lua code calling the global function `name` with `args` as string arguments.
+pub fn function_call(name: &str, args: &[String]) -> String {
+    format!("_G[{}]({})", quote(name), args.iter().map(|a| quote(a)).join(", "))
+}
+
+/// Comments out a leading `#!` line, keeping line numbers intact.
+fn strip_shebang(code: String) -> String {
+    if code.starts_with("#") {
+        format!("--{}", code)
+    } else {
+        code
+    }
+}
+
 fn strip_prefix(command: &str) -> String {
     command.trim_start_matches(LUA_PREFIX).to_string()
 }
@@ -317,3 +808,48 @@ impl Output for LuaVar {
         
     }
 }
+
+/// Passed to `precmd` hooks as `{real, user, sys, max_rss}`.
+impl<'lua> ToLua<'lua> for JobTimes {
+    fn to_lua(self, lua_ctx: rlua::Context<'lua>) -> rlua::Result<rlua::Value<'lua>> {
+        let times = lua_ctx.create_table()?;
+        times.set("real", self.real)?;
+        times.set("user", self.user)?;
+        times.set("sys", self.sys)?;
+        times.set("max_rss", self.max_rss)?;
+        Ok(rlua::Value::Table(times))
+    }
+}
+
+/// The handle returned by `luabster.spawn`.
+impl rlua::UserData for Coprocess {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("pid", |_, coprocess, ()| Ok(coprocess.pid));
+        methods.add_method_mut("write", |_, coprocess, data: String| {
+            coprocess.write(&data).map_err(rlua::Error::external)
+        });
+        methods.add_method_mut("read_line", |_, coprocess, ()| {
+            coprocess.read_line().map_err(rlua::Error::external)
+        });
+        methods.add_method_mut("close", |_, coprocess, ()| {
+            coprocess.close();
+            Ok(())
+        });
+        // Closes the input first, as most commands only exit once it ends
+        methods.add_method_mut("wait", |lua_ctx, coprocess, ()| {
+            coprocess.close();
+            Ok(with_cli_parser(lua_ctx, |p| p.wait_coprocess(coprocess.pid))?.flatten())
+        });
+    }
+}
+
+
+
+#[test]
+fn test_function_call() {
+    assert_eq!(function_call("greet", &[]), r#"_G["greet"]()"#);
+    assert_eq!(
+        function_call("greet", &["a b".to_string(), "say \"hi\"\n".to_string()]),
+        r#"_G["greet"]("a b", "say \"hi\"\n")"#
+    );
+}
@@ -1,7 +1,9 @@
 #include <lua.h>
 #include <lauxlib.h>
 #include <lualib.h>
+#include <stdio.h>
 #include <unistd.h>
+#include <fcntl.h>
 #include <stdlib.h>
 #include <string.h>
 
@@ -82,3 +84,133 @@ struct Child lua_runner_run_command(void* l, struct Child *child)
     return *child;
 }
 
+
+
+extern int run_subshell(void *parser, const char *cmd, int cmdlen);
+
+struct Child subshell_spawn_command(const char *command, uint32_t len, int is_first, int is_last)
+{
+    struct Child c = {
+        .cmd = strndup(command, len),
+        .pid = -1,
+        .stdin = { -1, -1 },
+        .stdout = { -1, -1 },
+        .stderr = { -1, -1 },
+        .is_first = is_first,
+        .is_last = is_last,
+    };
+
+    if (!is_last)
+        pipe(c.stdout);
+
+    return c;
+}
+
+/* Sets or clears `FD_CLOEXEC` on `fd`, deciding whether the commands the shell
+ * executes inherit it. */
+int set_cloexec(int fd, int on)
+{
+    int flags = fcntl(fd, F_GETFD);
+    if (flags < 0)
+        return -1;
+
+    return fcntl(fd, F_SETFD, on ? flags | FD_CLOEXEC : flags & ~FD_CLOEXEC);
+}
+
+/* Prepares the subshell of a process substitution. `<(...)` writes to the
+ * pipe in `stdout`, `>(...)` reads from the pipe in `stdin`. The other end is
+ * left open for the command the substitution is an argument of, but closed on
+ * exec so that no other command holds it open. */
+struct Child subshell_spawn_substitution(const char *command, uint32_t len, int is_input)
+{
+    struct Child c = subshell_spawn_command(command, len, 1, !is_input);
+
+    if (is_input) {
+        set_cloexec(c.stdout[PIPE_READ], 1);
+    } else {
+        pipe(c.stdin);
+        set_cloexec(c.stdin[PIPE_WRITE], 1);
+    }
+
+    return c;
+}
+
+/* Prepares a coprocess, whose stdin and stdout are both pipes to the shell.
+ * The shell's ends are closed on exec, like those of process substitutions. */
+struct Child subshell_spawn_coprocess(const char *command, uint32_t len)
+{
+    struct Child c = subshell_spawn_command(command, len, 1, 0);
+
+    pipe(c.stdin);
+    set_cloexec(c.stdout[PIPE_READ], 1);
+    set_cloexec(c.stdin[PIPE_WRITE], 1);
+
+    return c;
+}
+
+/* Forks a subshell running the commands in `child->cmd`. It reads from
+ * `stdin[PIPE_READ]` and writes to `stdout[PIPE_WRITE]` when those are set. */
+struct Child subshell_run_command(void *parser, struct Child *child)
+{
+    fflush(stdout);
+    if ((child->pid = fork()) == 0)
+    {
+        if (child->stdin[PIPE_READ] >= 0)
+        {
+            dup2(child->stdin[PIPE_READ], STDIN_FILENO);
+            close(child->stdin[PIPE_READ]);
+        }
+        if (child->stdout[PIPE_WRITE] >= 0)
+            dup2(child->stdout[PIPE_WRITE], STDOUT_FILENO);
+        if (child->stdout[PIPE_READ] >= 0)
+            close(child->stdout[PIPE_READ]);
+        if (child->stdin[PIPE_WRITE] >= 0)
+            close(child->stdin[PIPE_WRITE]);
+
+        exit(run_subshell(parser, child->cmd, strlen(child->cmd)));
+    }
+
+    if (child->stdin[PIPE_READ] >= 0)
+        close(child->stdin[PIPE_READ]);
+    // A redirection's file is closed by its owner, only our own pipe is closed here
+    if (child->stdout[PIPE_READ] >= 0)
+        close(child->stdout[PIPE_WRITE]);
+
+    free(child->cmd);
+    child->cmd = NULL;
+
+    return *child;
+}
+
+/* Points stdout at `fd` for commands run in the shell itself, returning a
+ * copy of the previous stdout for `restore_stdout`. */
+int redirect_stdout(int fd)
+{
+    fflush(stdout);
+    int saved = dup(STDOUT_FILENO);
+    dup2(fd, STDOUT_FILENO);
+    return saved;
+}
+
+void restore_stdout(int saved)
+{
+    fflush(stdout);
+    dup2(saved, STDOUT_FILENO);
+    close(saved);
+}
+
+/* Points stdin at `fd`, which is closed, for a builtin reading from a pipe.
+ * Returns a copy of the previous stdin for `restore_stdin`. */
+int redirect_stdin(int fd)
+{
+    int saved = dup(STDIN_FILENO);
+    dup2(fd, STDIN_FILENO);
+    close(fd);
+    return saved;
+}
+
+void restore_stdin(int saved)
+{
+    dup2(saved, STDIN_FILENO);
+    close(saved);
+}
@@ -4,34 +4,41 @@
 
 use std::{
     io::{self, Write},
-    error::Error
+    error::Error,
 };
 
 pub mod parser;
 pub mod lua_parser;
 pub mod log;
 pub mod termio;
+pub mod line_editor;
 pub mod input_parser;
 pub mod completions;
 pub mod config;
 pub mod prompt;
 pub mod expand;
+pub mod correction;
+pub mod command_hash;
+pub mod directories;
+pub mod frecency;
+pub mod options;
+pub mod traps;
+pub mod coproc;
+pub mod timing;
+pub mod printf;
+pub mod condition;
+pub mod limits;
 
 use crate::{
     parser::*,
     log::*,
+    lua_parser::HookReply,
 };
 
 
 
 
 
-const REPLACE_BASH_COMMAND: usize = 0;
-const REPLACE_LUA_COMMAND: usize = 1;
-const EDIT_COMMAND: usize = 2;
-const ABORT_COMMAND: usize = 3;
-
-
 extern "C" {
     fn signal_setup(p: *mut std::ffi::c_void);
 }
@@ -39,11 +46,15 @@ extern "C" {
 
 struct General {
     welcome_msg: Option<String>,
+    correction_menu: bool,
+    correction_candidates: usize,
 }
 
 const fn general_conf<'a>() -> &'a [config::ConfigParam<'a>] {
     & tag!{"general",
-        "welcome_message"   =>  ""
+        "welcome_message"   =>  "",
+        "correction_menu"   =>  true,
+        "correction_candidates" => 3,
     }
 }
 
@@ -59,29 +70,66 @@ impl<'a> config::Configurable<'a> for General {
                 _ => (),
             }
         }
+        if let Some(config::ConfigType::Toggle(b)) = configs.get("general.correction_menu") {
+            self.correction_menu = *b;
+        }
+        if let Some(config::ConfigType::Number(n)) = configs.get("general.correction_candidates") {
+            self.correction_candidates = *n as usize;
+        }
     }
 }
 
+#[derive(Default)]
+struct Args {
+    lua_script: Option<(String, Vec<String>)>,
+    login: bool,
+    norc: bool,
+    rcfile: Option<String>,
+}
+
 fn main() -> Result<(), Box<dyn Error>> {   
-    parse_args();
+    let args = parse_args();
 
     let home_dir = home::home_dir().unwrap().display().to_string();
     let mut cli_parser = CliParser::new(&home_dir);
-    let mut general_confs = General { welcome_msg: None };
+    let mut general_confs = General { welcome_msg: None, correction_menu: true, correction_candidates: 3 };
 
     config::configure(&mut [&mut general_confs], &cli_parser);
 
-    _ = cli_parser.parse_inputs(&format!("source {}/.luabster/luabster.conf", home_dir));
-
     unsafe {
         signal_setup(&mut cli_parser as *mut CliParser as *mut std::ffi::c_void);
     }
+    cli_parser.connect_lua_api();
+
+    if let Some((script, script_args)) = args.lua_script {
+        let status = cli_parser.run_lua_script(&script, &script_args);
+        cli_parser.shut_down(status);
+    }
+
+    cli_parser.login = args.login;
+
+    if args.login {
+        source_config_files(&mut cli_parser, &home_dir, config::PROFILE_FILE);
+    }
+
+    if let Some(rcfile) = &args.rcfile {
+        cli_parser.source_file(rcfile);
+    } else if !args.norc {
+        source_config_files(&mut cli_parser, &home_dir, config::RC_FILE);
+    }
 
     if let Some(m) = general_confs.welcome_msg {
         println!("{}", m);
     }
 
+    cli_parser.interactive = true;
+    let mut last_command = String::new();
+    let mut last_times = timing::JobTimes::default();
+
     loop {
+        cli_parser.run_pending_traps();
+        cli_parser.run_hooks("precmd", (last_command.clone(), cli_parser.last_status(), last_times.real, last_times));
+
         let prompt = cli_parser.prompt.get(&home_dir);
         display_prompt(&prompt);
 
@@ -89,48 +137,36 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         log!(LogLevel::Debug, "Input received: {}", command);
 
-        match cli_parser.input_parser.check_quit(&command) {
-            Err(_) => {
-                break;
-            },
-            Ok(_) => ()
-        };
+        if command.is_empty() {
+            continue;
+        }
+
+        cli_parser.run_hooks("preexec", command.clone());
+        let timer = timing::Timer::start();
+
+        let res = cli_parser.parse_inputs(&command);
 
-        if let Err(e) = cli_parser.parse_inputs(&command) {
+        last_times = timer.elapsed();
+        cli_parser.prompt.set_last_duration(last_times.real);
+        last_command = command.clone();
+
+        if let Err(e) = res {
             match e {
                 Errors::NoProgramFound(p) => {
-                    let (b_corr, b_corr_p) = CliParser::get_possible_correction(&p);
-
-                    //let l_corr = lua_parser.get_possible_correction(&p);
-
-                    let options = [
-                        &format!("{} in {}", b_corr, b_corr_p),
-                        &format!("{} in lua", "None"/*l_corr*/),
-                        "Edit",
-                        "Abort"
-                    ];
-
-                    match termio::get_choice(&options, false) {
-                        Ok(c) => {
-                            let retry = match c {
-                                REPLACE_BASH_COMMAND => { if b_corr != "No solution found" { replace_command(&mut command, &p, &b_corr); true } else { false }  },
-                                REPLACE_LUA_COMMAND => false,//replace_command(&mut command, &p, &l_corr)
-                                EDIT_COMMAND => { termio::edit_command(&mut command)?; true },
-                                ABORT_COMMAND => false,
-                                _ => false,
-                            };
-
-                            if retry {
-                                if let Err(e) = cli_parser.parse_inputs(&command) {
-                                    println!("{:?}", e);
-                                }
-
-                                cli_parser.input_parser.replace_last(&command);
+                    match cli_parser.run_command_not_found_hooks(&p, &command) {
+                        Some(HookReply::Handled) => (),
+                        Some(HookReply::Replace(replacement)) => {
+                            command = replacement;
+                            if let Err(e) = cli_parser.parse_inputs(&command) {
+                                println!("{:?}", e);
                             }
+
+                            cli_parser.input_parser.replace_last(&command);
+                        },
+                        None if !general_confs.correction_menu => {
+                            eprintln!("luabster: {}: command not found", p);
                         },
-                        Err(_) => {
-                            //println!("{:?}", e);
-                        }
+                        None => correction_menu(&mut cli_parser, &mut command, &p, general_confs.correction_candidates)?,
                     }
                 },
                 _ => {
@@ -144,15 +180,92 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 
-fn parse_args() {
-    let argv = std::env::args().collect::<Vec<String>>();
-    for i in 0..argv.len() {
-        let arg = &argv[i];
-        if arg == "-d" {
-            let level = argv[i + 1].clone();
-            set_loglevel(level.parse().unwrap());
+fn parse_args() -> Args {
+    let mut argv = std::env::args();
+    // login(1) starts login shells with a leading '-' in argv[0]
+    let mut args = Args {
+        login: argv.next().is_some_and(|argv0| argv0.starts_with('-')),
+        ..Default::default()
+    };
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-d" => {
+                let level = argv.next().unwrap();
+                set_loglevel(level.parse().unwrap());
+            },
+            "-l" => {
+                // Everything after the script path is passed on to the script
+                match argv.next() {
+                    Some(script) => args.lua_script = Some((script, argv.collect())),
+                    None => {
+                        eprintln!("luabster: -l requires a script path");
+                        std::process::exit(2);
+                    }
+                }
+                break;
+            },
+            "--login" => args.login = true,
+            "--norc" => args.norc = true,
+            "--rcfile" => {
+                match argv.next() {
+                    Some(rcfile) => args.rcfile = Some(rcfile),
+                    None => {
+                        eprintln!("luabster: --rcfile requires a file path");
+                        std::process::exit(2);
+                    }
+                }
+            },
+            _ => (),
         }
     }
+
+    args
+}
+
+fn source_config_files(cli_parser: &mut CliParser, home_dir: &str, name: &str) {
+    for file in config::config_files(home_dir, name) {
+        log!(LogLevel::Debug, "Sourcing {}", file);
+        cli_parser.source_file(&file);
+    }
+}
+
+fn correction_menu(cli_parser: &mut CliParser, command: &mut String, p: &str, max_candidates: usize) -> Result<(), Box<dyn Error>> {
+    let corrections = cli_parser.get_possible_corrections(p, max_candidates);
+
+    let mut options: Vec<String> = corrections.iter().map(|c| format!("{} in {}", c.name, c.origin)).collect();
+    let edit_command = options.len();
+    options.push("Edit".to_string());
+    options.push("Abort".to_string());
+
+    let options: Vec<&str> = options.iter().map(String::as_str).collect();
+
+    match termio::get_choice(&options, 0, false) {
+        Ok(c) => {
+            let retry = if c < corrections.len() {
+                replace_command(command, p, &corrections[c].name);
+                true
+            } else if c == edit_command {
+                // Ctrl-C while editing aborts
+                termio::edit_command(command).is_ok()
+            } else {
+                false
+            };
+
+            if retry {
+                if let Err(e) = cli_parser.parse_inputs(command) {
+                    println!("{:?}", e);
+                }
+
+                cli_parser.input_parser.replace_last(command);
+            }
+        },
+        Err(_) => {
+            //println!("{:?}", e);
+        }
+    }
+
+    Ok(())
 }
 
 fn display_prompt(prompt: &str) {
@@ -0,0 +1,95 @@
+use crate::{
+    config,
+    tag,
+};
+
+
+/// Option names in `set -o` order, with their single letter `set` flags.
+pub const OPTION_NAMES: [(&str, Option<char>); 5] = [
+    ("errexit", Some('e')),
+    ("noclobber", Some('C')),
+    ("nounset", Some('u')),
+    ("pipefail", None),
+    ("xtrace", Some('x')),
+];
+
+
+impl<'a> config::Configurable<'a> for ShellOptions {
+    fn get_configs(&self) -> &'a [config::ConfigParam<'a>] {
+        & tag!{"options",
+            "errexit"   => false,
+            "noclobber" => false,
+            "nounset"   => false,
+            "pipefail"  => false,
+            "xtrace"    => false,
+        }
+    }
+
+    fn with_config(&mut self, configs: &config::Configs) {
+        for (name, _) in OPTION_NAMES {
+            if let Some(config::ConfigType::Toggle(b)) = configs.get(format!("options.{}", name).as_str()) {
+                self.set(name, *b);
+            }
+        }
+    }
+}
+
+/// The runtime options toggled with `set`.
+#[derive(Clone, Debug, Default)]
+pub struct ShellOptions {
+    /// Exit when a command fails.
+    pub errexit: bool,
+    /// Refuse to overwrite existing files with `>`.
+    pub noclobber: bool,
+    /// Treat expanding an unset variable as an error.
+    pub nounset: bool,
+    /// The status of a pipeline is that of its last failing command.
+    pub pipefail: bool,
+    /// Print each command before it is run.
+    pub xtrace: bool,
+}
+
+impl ShellOptions {
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "noclobber" => Some(self.noclobber),
+            "nounset" => Some(self.nounset),
+            "pipefail" => Some(self.pipefail),
+            "xtrace" => Some(self.xtrace),
+            _ => None
+        }
+    }
+
+    /// Sets the option `name`, returning false if there is no such option.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let option = match name {
+            "errexit" => &mut self.errexit,
+            "noclobber" => &mut self.noclobber,
+            "nounset" => &mut self.nounset,
+            "pipefail" => &mut self.pipefail,
+            "xtrace" => &mut self.xtrace,
+            _ => return false
+        };
+
+        *option = value;
+        true
+    }
+
+    /// The option toggled by the `set` flag `flag`.
+    pub fn name_of_flag(flag: char) -> Option<&'static str> {
+        OPTION_NAMES.iter().find(|(_, f)| *f == Some(flag)).map(|(n, _)| *n)
+    }
+}
+
+
+#[test]
+fn test_set_options() {
+    let mut options = ShellOptions::default();
+
+    assert!(options.set(ShellOptions::name_of_flag('e').unwrap(), true));
+    assert!(!options.set("nosuchoption", true));
+    assert_eq!(options.get("errexit"), Some(true));
+    assert_eq!(options.get("pipefail"), Some(false));
+    assert_eq!(ShellOptions::name_of_flag('C'), Some("noclobber"));
+}
@@ -4,6 +4,9 @@ use std::{
     os::unix::io::*,
     env,
     collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    rc::Rc,
 };
 
 
@@ -18,6 +21,18 @@ use crate::{
     prompt,
     config::Configurable,
     expand,
+    correction,
+    command_hash,
+    directories,
+    frecency,
+    options,
+    traps,
+    coproc::{self, Coprocess},
+    timing,
+    printf,
+    condition,
+    limits,
+    termio,
 };
 
 use itertools::Itertools;
@@ -28,7 +43,63 @@ type Command = Vec<String>;
 type Commands = Vec<Command>;
 //type Job = Vec<ChildProcess>;
 type Job = Vec<i32>;
-pub type BuiltInFunctionHandler<'a> = fn(&mut CliParser<'a>, &Command);
+pub type BuiltInFunction<'a> = fn(&mut CliParser<'a>, &Command, &mut Streams) -> i32;
+
+/// A command run by the shell itself. Returns the exit status.
+pub trait BuiltInHandler<'a> {
+    fn run(&self, parser: &mut CliParser<'a>, command: &Command, streams: &mut Streams) -> i32;
+}
+
+impl<'a, F> BuiltInHandler<'a> for F where F: Fn(&mut CliParser<'a>, &Command, &mut Streams) -> i32 {
+    fn run(&self, parser: &mut CliParser<'a>, command: &Command, streams: &mut Streams) -> i32 {
+        self(parser, command, streams)
+    }
+}
+
+/// The standard streams of a builtin.
+pub struct Streams {
+    /// Unbuffered, so that reading a line leaves the rest to the next command.
+    pub stdin: Box<dyn Read>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+}
+
+impl Streams {
+    /// The shell's own stdin, stdout and stderr, which are redirected or
+    /// connected to the pipeline when a builtin runs.
+    pub fn standard() -> Self {
+        Self {
+            stdin: Box::new(Fd(0)),
+            stdout: Box::new(BufWriter::new(Fd(1))),
+            stderr: Box::new(Fd(2)),
+        }
+    }
+}
+
+/// A file descriptor that is borrowed, and so left open when dropped.
+struct Fd(RawFd);
+
+impl Fd {
+    fn file(&self) -> mem::ManuallyDrop<File> {
+        mem::ManuallyDrop::new(unsafe { File::from_raw_fd(self.0) })
+    }
+}
+
+impl Read for Fd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file().read(buf)
+    }
+}
+
+impl Write for Fd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum Errors {
@@ -42,13 +113,26 @@ pub enum Errors {
 #[derive(Debug)]
 enum ChildCommand {
     Bash(std::process::Command),
-    Lua(Child)
+    Lua(Child),
+    Subshell(Child),
+    /// A builtin not ending a foreground pipeline, run in a forked copy of the shell.
+    Builtin(Child, Command),
 }
 
 #[derive(Debug)]
 enum ChildProcess {
     Bash(std::process::Child),
     Lua(Child),
+    Subshell(Child),
+}
+
+/// The grouping constructs, which run a list of commands as one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupKind {
+    /// `( ... )`, run in a forked copy of the shell.
+    Subshell,
+    /// `{ ...; }`, run in the current shell unless part of a pipeline.
+    Group,
 }
 
 
@@ -70,6 +154,24 @@ pub struct Child {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ParseError;
 
+/// What a command name refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    Alias(String),
+    Builtin,
+    Executable(String),
+    LuaFunction,
+}
+
+/// A suggested replacement for a mistyped command.
+#[derive(Clone, Debug)]
+pub struct Correction {
+    pub name: String,
+    /// Where the suggestion was found, e.g. `builtin`, `lua` or a directory.
+    pub origin: String,
+    pub score: f64,
+}
+
 
 enum OutputType {
     NoOutput,
@@ -106,7 +208,7 @@ enum State {
 
 pub struct CliParser<'a> {
     jobs: Vec<Job>,
-    builtin_handlers: HashMap<&'a str, BuiltInFunctionHandler<'a>>,
+    builtin_handlers: HashMap<&'a str, Rc<dyn BuiltInHandler<'a> + 'a>>,
     aliases: HashMap<String, String>,
     cur_job: Option<usize>,
     lua_parser: lua_parser::LuaParser,
@@ -114,15 +216,63 @@ pub struct CliParser<'a> {
     pub input_parser: input_parser::InputParser,
     pub prompt: prompt::Prompt<'a>,
     children: std::collections::HashMap<i32, std::process::Child>,
+    exit_statuses: HashMap<i32, i32>,
+    last_status: i32,
+    argument_correction: correction::ArgumentCorrection,
+    directories: directories::Directories,
+    options: options::ShellOptions,
+    /// Commands run when a signal arrives or on `EXIT`/`ERR`.
+    traps: HashMap<String, String>,
+    in_trap: bool,
+    /// Whether this is a forked `( ... )` subshell.
+    in_subshell: bool,
+    /// Whether this is a login shell, which sources the logout files on exit.
+    pub login: bool,
+    /// Whether commands are read from the prompt, whose history is saved on exit.
+    pub interactive: bool,
+    /// Our ends of the pipes of the current command's process substitutions.
+    substitution_fds: Vec<OwnedFd>,
+    /// Exit statuses of the current command's process substitutions, `None`
+    /// while they run.
+    substitution_statuses: HashMap<i32, Option<i32>>,
+    /// Coprocesses started with `coproc`, by name.
+    coprocesses: HashMap<String, Coprocess>,
+    /// Exit statuses of all coprocesses, `None` while they run.
+    coprocess_statuses: HashMap<i32, Option<i32>>,
+    /// The builtin a forked child is about to run instead of a subshell.
+    forked_builtin: Option<Command>,
+    /// Processes of pipelines whose builtin is still running in the shell,
+    /// before their job is waited for.
+    pipeline_pids: Vec<i32>,
+    /// The positional parameters `$1`, `$2`, ...
+    positional: Vec<String>,
+    /// Shell variables, which unlike environment variables commands don't see.
+    variables: HashMap<String, String>,
+    /// The match and groups of the last `=~` in `[[ ... ]]`, as in `BASH_REMATCH`.
+    rematch: Vec<String>,
+    /// How many `select` loops are running.
+    loop_depth: usize,
+    /// How many loops `break` is still to leave.
+    loop_break: usize,
 }
 
 extern "C" {
     fn sig_kill(pid: u32, sig: i32);
     fn signal_is_stopped(pids: *const u32, num_pids: u32) -> bool;
     fn lua_runner_run_command(l: *mut std::ffi::c_void, c: *mut Child) -> Child;
+    fn subshell_spawn_command(cmd: *const std::ffi::c_uchar, len: u32, is_first: i32, is_last: i32) -> Child;
+    fn subshell_spawn_substitution(cmd: *const std::ffi::c_uchar, len: u32, is_input: i32) -> Child;
+    fn subshell_spawn_coprocess(cmd: *const std::ffi::c_uchar, len: u32) -> Child;
+    fn subshell_run_command(p: *mut std::ffi::c_void, c: *mut Child) -> Child;
+    fn set_cloexec(fd: i32, on: i32) -> i32;
+    fn redirect_stdout(fd: i32) -> i32;
+    fn restore_stdout(saved: i32);
+    fn redirect_stdin(fd: i32) -> i32;
+    fn restore_stdin(saved: i32);
     fn try_wait_process(pid: u32) -> i32;
     fn enter_critical_section();
     fn exit_critical_section();
+    fn wait_for_signal();
     static sig_CONT: i32;
     static PROCESS_EXITED: i32;
     static PROCESS_STOPPED: i32;
@@ -130,27 +280,64 @@ extern "C" {
 }
 
 const LUA_PREFIX: &str = "!";
-const STR_SIM_THRESHOLD: f64 = 0.95;
+const PWD_KEY: &str = "PWD";
+const OLDPWD_KEY: &str = "OLDPWD";
+const PS4_KEY: &str = "PS4";
+const DEFAULT_PS4: &str = "+ ";
+const IFS_KEY: &str = "IFS";
+const DEFAULT_IFS: &str = " \t\n";
+const REPLY_KEY: &str = "REPLY";
+const REMATCH_KEY: &str = "BASH_REMATCH";
+const PS3_KEY: &str = "PS3";
+const DEFAULT_PS3: &str = "#? ";
+const STR_SIM_THRESHOLD: f64 = 0.8;
 
 
 impl<'a: 'b, 'b, 'c> config::ConfigurationLoader<'a, 'b> for CliParser<'c> {
     fn load_config(&self, params: &[&'a str]) -> HashMap<&'b str, String> {
-        self.lua_parser.load_config(params, &home::home_dir().unwrap().to_string_lossy())
+        let home_dir = home::home_dir().unwrap().to_string_lossy().to_string();
+        self.lua_parser.load_config(params, &config::config_files(&home_dir, config::CONFIG_FILE))
     }
 }
 
 
 impl<'a> CliParser<'a> {
-    const BUILTIN_COMMANDS: [(&'static str, BuiltInFunctionHandler<'a>); 9] = [
+    const BUILTIN_COMMANDS: [(&'static str, BuiltInFunction<'a>); 35] = [
         ("exit", Self::exit),
         ("cd", Self::cd),
+        ("pushd", Self::pushd),
+        ("popd", Self::popd),
+        ("dirs", Self::dirs),
+        ("z", Self::z),
         ("fg", Self::fg),
         ("bg", Self::bg),
+        ("jobs", Self::jobs),
         ("alias", Self::alias),
+        ("unalias", Self::unalias),
+        ("abbr", Self::abbr),
         ("source", Self::source),
         ("export", Self::export),
         ("eval", Self::eval),
         ("luabster_update", Self::update_config),
+        ("hash", Self::hash),
+        ("type", Self::type_of),
+        ("which", Self::which),
+        ("command", Self::command),
+        ("builtin", Self::builtin),
+        ("set", Self::set),
+        ("trap", Self::trap),
+        ("echo", Self::echo),
+        ("printf", Self::printf),
+        ("test", Self::test),
+        ("[", Self::test),
+        ("true", Self::always_true),
+        ("false", Self::always_false),
+        ("read", Self::read),
+        ("shift", Self::shift),
+        ("break", Self::break_loop),
+        ("exec", Self::exec),
+        ("umask", Self::umask),
+        ("ulimit", Self::ulimit),
     ];
 
     pub fn get_builtin_commands() -> Vec<&'static str> {
@@ -168,35 +355,76 @@ impl<'a> CliParser<'a> {
             input_parser: input_parser::InputParser::new(home_dir),
             prompt: prompt::Prompt::new(),
             children: HashMap::new(),
+            exit_statuses: HashMap::new(),
+            last_status: 0,
+            argument_correction: correction::ArgumentCorrection::default(),
+            directories: directories::Directories::default(),
+            options: options::ShellOptions::default(),
+            traps: HashMap::new(),
+            in_trap: false,
+            in_subshell: false,
+            login: false,
+            interactive: false,
+            substitution_fds: Vec::new(),
+            substitution_statuses: HashMap::new(),
+            coprocesses: HashMap::new(),
+            coprocess_statuses: HashMap::new(),
+            forked_builtin: None,
+            pipeline_pids: Vec::new(),
+            positional: Vec::new(),
+            variables: HashMap::new(),
+            rematch: Vec::new(),
+            loop_depth: 0,
+            loop_break: 0,
         };
 
+        if let Ok(cwd) = env::current_dir() {
+            env::set_var(PWD_KEY, cwd);
+        }
+
         for (n, f) in Self::BUILTIN_COMMANDS {
             parser.bind_builtin_command(n, f);
         }
 
-        parser.update_config(&Vec::new());
+        parser.update_config(&Vec::new(), &mut Streams::standard());
 
         parser
     }
 
-    pub fn bind_builtin_command(&mut self, command: &'a str, handler: BuiltInFunctionHandler<'a>) {
-        self.builtin_handlers.insert(command, handler);
+    pub fn bind_builtin_command(&mut self, command: &'a str, handler: impl BuiltInHandler<'a> + 'a) {
+        self.builtin_handlers.insert(command, Rc::new(handler));
+    }
+
+    /// Lets the `luabster` Lua API execute commands in this shell, which must not move afterwards.
+    pub fn connect_lua_api(&mut self) {
+        let this = self as *mut CliParser as *mut std::ffi::c_void;
+        self.lua_parser.set_cli_parser(this);
+    }
+
+    pub fn run_lua_script(&self, path: &str, args: &[String]) -> i32 {
+        self.lua_parser.run_script(path, args)
     }
 
     pub fn read_config<'b>(&mut self, params: &[&'b str], home_dir: &str) -> HashMap<&'b str, String> {
-        self.lua_parser.load_config(params, home_dir)
+        self.lua_parser.load_config(params, &config::config_files(home_dir, config::CONFIG_FILE))
     }
 
     fn configure(&mut self) {
         let mut new_prompt = self.prompt.clone();
         let mut new_input_parser = self.input_parser.clone();
         let mut lua_scripts = self.lua_parser.scripts.clone();
+        let mut argument_correction = self.argument_correction.clone();
+        let mut directories = self.directories.clone();
+        let mut options = self.options.clone();
         
         let mut configurables = [
             &mut new_prompt as &mut dyn Configurable,
             &mut new_input_parser as &mut dyn Configurable,
             &mut crate::termio::Termio as &mut dyn Configurable,
             &mut lua_scripts as &mut dyn Configurable,
+            &mut argument_correction as &mut dyn Configurable,
+            &mut directories as &mut dyn Configurable,
+            &mut options as &mut dyn Configurable,
         ];
 
         config::configure(&mut configurables, self);
@@ -204,47 +432,115 @@ impl<'a> CliParser<'a> {
         self.prompt = new_prompt;
         self.input_parser = new_input_parser;
         self.lua_parser.scripts = lua_scripts;
+        self.argument_correction = argument_correction;
+        self.directories = directories;
+        self.options = options;
     }
 
     pub fn parse_inputs(&mut self, command: &str) -> Result<(), Errors> {
         if command.is_empty() {
             return Ok(());
         }
-        let run_in_bg = Self::run_in_bg(command);
-        let mut command = Self::expand_string(command);
+        let command = expand_aliases(&self.aliases, command, &mut Vec::new(), &|name, args| self.lua_parser.call_alias(name, args));
+        let run_in_bg = Self::run_in_bg(&command);
+
+        if self.options.nounset {
+            if let Err(name) = expand::expand_variables(&command, &|name| self.variable(name)) {
+                eprintln!("luabster: {}: unbound variable", name);
+                self.last_status = 1;
+                self.check_errexit();
+                return Ok(());
+            }
+        }
+        let mut command = command.to_string();
 
         if run_in_bg {
             command.pop(); // Remove final '&' from command
         }
-        self.should_wait = !run_in_bg;
 
-        for block in command.split(";") {
-            for cmd in block.split("&&") {
+        'blocks: for (block, _) in split_unquoted(&command, &[";"]) {
+            if block.trim().is_empty() {
+                continue;
+            }
+            let and_list = split_unquoted(block, &["&&"]);
+            let last_cmd = and_list.len() - 1;
+
+            for (i, (cmd, _)) in and_list.into_iter().enumerate() {
 
-                let mut args: (Commands, Option<Box<dyn Output>>) = self.parse_input(&cmd);
+                let (cmd, timer) = match strip_keyword(cmd, "time") {
+                    Some(rest) => match strip_keyword(rest, "-p") {
+                        Some(rest) => (rest, Some((timing::Timer::start(), timing::POSIX_TIMEFORMAT.to_string()))),
+                        None => {
+                            let format = env::var(timing::TIMEFORMAT_KEY).unwrap_or(timing::DEFAULT_TIMEFORMAT.to_string());
+                            (rest, Some((timing::Timer::start(), format)))
+                        }
+                    },
+                    None => (cmd, None),
+                };
 
-                for arg in &args.0 {
-                    if Self::check_validity_of_program(&arg) == false {
-                        return Err(Errors::NoProgramFound(arg[0].clone()));
+                // `[[ ... ]]` and `select` expand their parts themselves, the former
+                // as its words are neither split nor globbed, the latter as its
+                // commands are expanded anew for every choice
+                let expanded;
+                let cmd = match strip_keyword(cmd, "[[").or(strip_keyword(cmd, "select")) {
+                    Some(_) => cmd,
+                    None => {
+                        expanded = self.expand_string(cmd);
+                        &expanded
                     }
-                }
+                };
 
-                let mut commands = self.spawn_commands(&args.0);
-
-                unsafe { enter_critical_section(); }
-                match self.execute_commands(&mut commands, &mut args.1) {
-                    Ok(children) => {
-                        self.jobs.push(children);
-                        if self.should_wait {
-                            self.cur_job = Some(self.jobs.len() - 1);
-                            unsafe { exit_critical_section(); }
-                            self.wait_for_children_to_finish();
-                        } else {
-                            unsafe { exit_critical_section(); }
+                let res = if let Some(expression) = strip_keyword(cmd, "[[") {
+                    self.conditional(expression);
+                    Ok(())
+                } else if let Some(select) = strip_keyword(cmd, "select") {
+                    self.select(select);
+                    Ok(())
+                } else if let Some(coproc) = strip_keyword(cmd, "coproc") {
+                    self.coproc(coproc);
+                    Ok(())
+                } else if cmd.trim().is_empty() {
+                    Ok(())
+                } else {
+                    // Reset for every command, the previous job's end cleared it
+                    self.should_wait = !run_in_bg;
+                    match self.parse_input(cmd) {
+                        Ok(mut args) => self.run_commands(args.0, &mut args.1, true),
+                        Err(_) => {
+                            self.last_status = 1;
+                            Ok(())
                         }
                     }
-                    Err(_) => unsafe { exit_critical_section(); }
                 };
+                // The command has its own copies of the substitution pipes by now
+                self.substitution_fds.clear();
+                if run_in_bg {
+                    // They run alongside the job instead
+                    self.substitution_statuses.clear();
+                } else {
+                    self.wait_substitutions();
+                }
+
+                if let Some((timer, format)) = timer {
+                    eprintln!("{}", timer.elapsed().format(&format));
+                }
+
+                self.run_pending_traps();
+
+                // `break` skips the rest of the loop's commands
+                if self.loop_break > 0 {
+                    break 'blocks;
+                }
+
+                // Only the last command of an `&&` list can trigger errexit
+                if self.last_status != 0 {
+                    if i == last_cmd {
+                        self.run_trap(traps::ERR);
+                        self.check_errexit();
+                    }
+                    res?;
+                    break;
+                }
             };
         }
 
@@ -253,25 +549,209 @@ impl<'a> CliParser<'a> {
         Ok(())
     }
 
-    fn parse_input(&mut self, command: &str) -> (Commands, Option<Box<dyn Output>>) {
+    /// With errexit set, a failed command makes the shell exit with its status.
+    fn check_errexit(&mut self) {
+        if self.options.errexit && self.last_status != 0 {
+            self.shut_down(self.last_status);
+        }
+    }
+
+    /// Exits with `status`, however the shell is left. The exit hooks and the
+    /// `EXIT` trap run first, then a login shell sources its logout files and
+    /// an interactive one saves its history. Subshells only flush their output.
+    pub fn shut_down(&mut self, status: i32) -> ! {
+        if !self.in_subshell {
+            self.last_status = status;
+            self.run_hooks("exit", status);
+            self.run_trap(traps::EXIT);
+
+            if self.login {
+                let home_dir = home::home_dir().map(|p| p.display().to_string()).unwrap_or_default();
+                for file in config::config_files(&home_dir, config::LOGOUT_FILE) {
+                    self.source_file(&file);
+                }
+            }
+            if self.interactive {
+                self.input_parser.write_history();
+            }
+        }
+
+        _ = std::io::Write::flush(&mut std::io::stdout());
+        std::process::exit(status);
+    }
+
+    /// Runs the traps of the signals that arrived since the last call.
+    pub fn run_pending_traps(&mut self) {
+        while let Some(name) = traps::take_pending() {
+            self.run_trap(&name);
+        }
+    }
+
+    /// Runs the trap set for `name`, if any. `$?` is left as it was.
+    pub fn run_trap(&mut self, name: &str) {
+        if self.in_trap {
+            return;
+        }
+        self.in_trap = true;
+        let status = self.last_status;
+
+        match self.traps.get(name).cloned() {
+            Some(command) => {
+                if let Err(e) = self.parse_inputs(&command) {
+                    eprintln!("luabster: {} trap failed: {:?}", name, e);
+                }
+            },
+            None => _ = self.lua_parser.run_trap(name),
+        }
+
+        self.last_status = status;
+        self.in_trap = false;
+    }
+
+    /// Sets the trap command for `name`, replacing any Lua handler. An empty
+    /// command ignores the signal and `None` restores its default handling.
+    pub fn set_trap(&mut self, name: &str, command: Option<&str>) {
+        self.lua_parser.remove_trap(name);
+
+        match command {
+            Some(command) => {
+                self.traps.insert(name.to_string(), command.to_string());
+                if command.is_empty() {
+                    traps::ignore(name);
+                } else {
+                    traps::catch(name);
+                }
+            },
+            None => {
+                self.traps.remove(name);
+                traps::reset(name);
+            }
+        }
+    }
+
+    /// Runs a single pipeline. With `lua_fallback`, programs that can't be found
+    /// are run as Lua functions of the same name if such exist, and bare
+    /// directories are changed into when auto-cd is enabled.
+    fn run_commands(&mut self, mut commands: Commands, output: &mut Option<Box<dyn Output>>, lua_fallback: bool) -> Result<(), Errors> {
+        if lua_fallback && commands.len() == 1 && output.is_none() && self.is_auto_cd(&commands[0]) {
+            let cd = vec!["cd".to_string(), commands[0][0].clone()];
+            self.last_status = self.cd(&cd, &mut Streams::standard());
+            return Ok(());
+        }
+
+        if commands.len() == 1 && self.should_wait {
+            if let Some((GroupKind::Group, body, _)) = split_group(&commands[0][0]) {
+                return self.run_group(body, output);
+            }
+        }
+
+        for arg in commands.iter_mut() {
+            if Self::check_validity_of_program(&arg) == false {
+                if lua_fallback && self.lua_parser.is_function(&arg[0]) {
+                    *arg = vec![format!("{}{}", LUA_PREFIX, lua_parser::function_call(&arg[0], &arg[1..]))];
+                    continue;
+                }
+                self.last_status = 127;
+                return Err(Errors::NoProgramFound(arg[0].clone()));
+            }
+        }
+
+        for arg in commands.iter_mut() {
+            self.argument_correction.correct(arg);
+        }
+
+        if self.options.xtrace {
+            let ps4 = env::var(PS4_KEY).unwrap_or(DEFAULT_PS4.to_string());
+            for cmd in &commands {
+                eprintln!("{}{}", ps4, cmd.iter().map(|a| quote_word(a)).join(" "));
+            }
+        }
+
+        self.last_status = 0;
+        // A builtin ending a pipeline runs in the shell itself, so that e.g.
+        // `cd` or `read` affect it
+        let builtin = if self.should_wait && Self::is_builtin(commands.last().unwrap()) { commands.pop() } else { None };
+        let mut children = self.spawn_commands(&commands, builtin.is_some());
+
+        unsafe { enter_critical_section(); }
+        if let Some(builtin) = builtin {
+            let res = self.pipe_children(&mut children);
+            let (pids, stdin) = res.unwrap_or_default();
+            if !pids.is_empty() {
+                self.jobs.push(pids.clone());
+            }
+            self.pipeline_pids.extend(&pids);
+            unsafe { exit_critical_section(); }
+            drop(children);
+
+            let status = self.run_builtin_in_process(&builtin, stdin, output);
+            self.wait_for_pipeline(&pids);
+            let job_status = self.job_status(&pids);
+            self.last_status = if status == 0 && self.options.pipefail { job_status } else { status };
+            return Ok(());
+        }
+
+        let res = self.execute_commands(&mut children, output);
+        // The commands hold the pipes between them open until dropped
+        drop(children);
+        match res {
+            Ok(children) => {
+                let pids = children.clone();
+                self.jobs.push(children);
+                if self.should_wait {
+                    self.cur_job = Some(self.jobs.len() - 1);
+                    unsafe { exit_critical_section(); }
+                    self.wait_for_children_to_finish();
+                    self.last_status = self.job_status(&pids);
+                } else {
+                    unsafe { exit_critical_section(); }
+                }
+            }
+            Err(_) => unsafe { exit_critical_section(); }
+        };
+
+        Ok(())
+    }
+
+    fn parse_input(&mut self, command: &str) -> Result<(Commands, Option<Box<dyn Output>>), Errors> {
         let mut arguments: Commands = Vec::new();
         let mut output: Option<Box<dyn Output>> = None;
-        let cmds = command.split("|").collect::<Vec<&str>>();
-        let (_, last_cmd) = cmds.split_at(cmds.len()-1);
-        let last_cmd = last_cmd[0];
+        let cmds = split_unquoted(command, &["|"]);
+        let last_cmd = cmds.len() - 1;
         let mut out_file = None;
+        let mut last_stage = String::new();
 
-        for arg in cmds {
+        for (i, (arg, _)) in cmds.into_iter().enumerate() {
             let arg = arg.trim();
             if Self::is_lua_command(arg) {
                 arguments.push(vec![arg.to_owned()]);
                 continue;
             }
-            match Self::parse_command(arg) {
+            if let Some(opening) = arg.chars().next().filter(|_| group_delimiter(arg.as_bytes(), 0) == 1) {
+                let Some((_, _, rest)) = split_group(arg) else {
+                    eprintln!("luabster: syntax error: unmatched '{}'", opening);
+                    return Err(Errors::PipeFailure);
+                };
+                // Redirections after the group apply to all of it
+                let rest = rest.trim();
+                if i == last_cmd && rest.starts_with('>') {
+                    output = Self::create_output(rest, &mut self.lua_parser, self.options.noclobber)?;
+                } else if !rest.is_empty() {
+                    eprintln!("luabster: syntax error near '{}'", rest);
+                    return Err(Errors::PipeFailure);
+                }
+                arguments.push(vec![arg[..arg.len() - rest.len()].trim_end().to_string()]);
+                continue;
+            }
+            let arg = self.substitute_processes(arg);
+            if i == last_cmd {
+                last_stage = arg.clone();
+            }
+            match Self::parse_command(&arg) {
                 Ok(mut cmd) => {
                     // expand arguments
                     cmd = cmd.iter_mut().map(|a| crate::expand::expand_all(a)).collect();
-                    if arg == last_cmd && cmd.len() > 1 {
+                    if i == last_cmd && cmd.len() > 1 {
                         match cmd[cmd.len() - 2].as_str() {
                             ">" | ">>" => {
                                 out_file = Some(cmd.last().unwrap().to_owned());
@@ -280,12 +760,6 @@ impl<'a> CliParser<'a> {
                             _ => {}
                         }
                     }
-                    if let Some(a) = self.aliases.get(&cmd[0]) {
-                        if let Ok(mut exp_cmd) = Self::parse_command(a) {
-                            exp_cmd.append(&mut cmd.into_iter().dropping(1).collect());
-                            cmd = exp_cmd;
-                        }
-                    }
                     arguments.push(cmd);
                 },
                 Err(e) => {
@@ -297,10 +771,10 @@ impl<'a> CliParser<'a> {
 
         if let Some(file) = out_file {
             log!(LogLevel::Debug, "Creating output {}", file);
-            output = Self::create_output(command, &mut self.lua_parser);
+            output = Self::create_output(&last_stage, &mut self.lua_parser, self.options.noclobber)?;
         }
 
-        (arguments, output)
+        Ok((arguments, output))
     }
 
     fn run_in_bg(command: &str) -> bool {
@@ -436,24 +910,27 @@ impl<'a> CliParser<'a> {
         out
     }
 
-    fn create_output(command: &str, lua_parser: &mut lua_parser::LuaParser) -> Option<Box<dyn Output>> {
+    fn create_output(command: &str, lua_parser: &mut lua_parser::LuaParser, noclobber: bool) -> Result<Option<Box<dyn Output>>, Errors> {
         let _output: Option<Box<dyn Output>> = None;
 
-        match Self::get_output_file(command) {
+        Ok(match Self::get_output_file(command) {
             OutputType::AppendVariable(n) => lua_parser.append_to_variable(&n.trim()),
             OutputType::NewVariable(n)    => lua_parser.output_to_variable(&n.trim()),       
             OutputType::AppendFile(n)     => Self::append_file(&n.trim()).ok(),
-            OutputType::OverwriteFile(n)  => Self::overwrite_file(&n.trim()).ok(),
+            OutputType::OverwriteFile(n)  => Some(Self::overwrite_file(&n.trim(), noclobber)?),
             OutputType::NoOutput          => None
-        }
+        })
     }
 
-    fn overwrite_file(file_name: &str) -> Result<Box<dyn Output>, Errors> {
-        let file = OutFile::new(file_name);
+    fn overwrite_file(file_name: &str, noclobber: bool) -> Result<Box<dyn Output>, Errors> {
+        let file = OutFile::new(file_name, noclobber);
 
         match file {
             Ok(f) => Ok(f),
-            Err(_) => Err(Errors::FileOverwriteError)
+            Err(e) => {
+                eprintln!("luabster: {}: {}", file_name, e);
+                Err(Errors::FileOverwriteError)
+            }
         }
     }
 
@@ -466,13 +943,25 @@ impl<'a> CliParser<'a> {
         }
     }
 
-    fn spawn_commands(&mut self, commands: &Commands) -> Vec<ChildCommand> {
+    /// Prepares the processes of a pipeline. With `piped`, the last one's
+    /// output is piped to a builtin run in the shell.
+    fn spawn_commands(&mut self, commands: &Commands, piped: bool) -> Vec<ChildCommand> {
         let mut spawned_commands: Vec<ChildCommand> = Vec::new();
 
         for (i, cmd) in commands.iter().enumerate() {
             let first = i == 0;
-            let last = i == commands.len() - 1;
-            if self.check_builtin_command(cmd) == true {
+            let last = i == commands.len() - 1 && !piped;
+            if let Some((_, body, _)) = split_group(&cmd[0]) {
+                unsafe {
+                    spawned_commands.push(ChildCommand::Subshell(subshell_spawn_command(body.as_ptr(), body.len() as u32, first.into(), last.into())));
+                }
+                continue;
+            }
+            if Self::is_builtin(cmd) {
+                let text = cmd.iter().map(|a| quote_word(a)).join(" ");
+                unsafe {
+                    spawned_commands.push(ChildCommand::Builtin(subshell_spawn_command(text.as_ptr(), text.len() as u32, first.into(), last.into()), cmd.clone()));
+                }
                 continue;
             }
             if let Some(c) = self.lua_parser.parse(&cmd[0], first, last) {
@@ -493,6 +982,19 @@ impl<'a> CliParser<'a> {
             process.args(&command[1..]);
         }
 
+        // The shell's pipe ends are closed on exec, except for a command naming them
+        let fds: Vec<i32> = command.iter().flat_map(|arg| dev_fds(arg)).collect();
+        if !fds.is_empty() {
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(&mut process, move || {
+                    for fd in &fds {
+                        set_cloexec(*fd, 0);
+                    }
+                    Ok(())
+                });
+            }
+        }
+
         process
     }
 
@@ -505,6 +1007,9 @@ impl<'a> CliParser<'a> {
             },
             ChildCommand::Lua(last_command) => {
                 last_command.stdout[PIPE_READ] = outfile.as_mut().unwrap().to_fd();
+            },
+            ChildCommand::Subshell(last_command) | ChildCommand::Builtin(last_command, _) => {
+                last_command.stdout[PIPE_WRITE] = outfile.as_mut().unwrap().to_fd();
             }
         }
 
@@ -515,7 +1020,8 @@ impl<'a> CliParser<'a> {
         let mut retval: Result<Job, std::io::Error> = Err(std::io::Error::new(std::io::ErrorKind::Other, "No Children"));
 
         if commands.len() > 0 {
-            let (mut children, prev_stdout) = self.pipe_children(commands)?;
+            let len = commands.len();
+            let (mut children, prev_stdout) = self.pipe_children(&mut commands[..len - 1])?;
             let last_cmd: &mut ChildCommand = commands.last_mut().unwrap();
 
             match last_cmd {
@@ -526,7 +1032,7 @@ impl<'a> CliParser<'a> {
                         last_cmd.stdin(std::process::Stdio::inherit());
                     }
                 },
-                ChildCommand::Lua(last_cmd)  => {
+                ChildCommand::Lua(last_cmd) | ChildCommand::Subshell(last_cmd) | ChildCommand::Builtin(last_cmd, _) => {
                     if let Some(stdout) = prev_stdout {
                         last_cmd.stdin[PIPE_READ] = stdout;
                     }
@@ -541,7 +1047,7 @@ impl<'a> CliParser<'a> {
             } else {
                 match last_cmd {
                     ChildCommand::Bash(last_cmd) => {last_cmd.stdout(std::process::Stdio::inherit());},
-                    ChildCommand::Lua(_)  => ()
+                    ChildCommand::Lua(_) | ChildCommand::Subshell(_) | ChildCommand::Builtin(..) => ()
                 }
             }
 
@@ -556,12 +1062,13 @@ impl<'a> CliParser<'a> {
     }
 
 
-    fn pipe_children(&mut self, commands: &mut Vec<ChildCommand>) -> Result<(Vec<i32>, Option<i32>), std::io::Error> {
+    /// Starts `commands` with the output of each piped to the next, returning
+    /// their pids and the read end of the last one's output pipe.
+    fn pipe_children(&mut self, commands: &mut [ChildCommand]) -> Result<(Vec<i32>, Option<i32>), std::io::Error> {
         let mut children = Vec::new();
         let mut prev_stdout = None;
 
-        for i in 0..(commands.len() - 1) {
-            let cmd = &mut commands[i];
+        for cmd in commands.iter_mut() {
             match cmd {
                 ChildCommand::Bash(cmd) => {
                     if let Some(prev_stdout) = prev_stdout {
@@ -574,7 +1081,7 @@ impl<'a> CliParser<'a> {
                     cmd.stdout(std::process::Stdio::piped());
                     cmd.stderr(std::process::Stdio::inherit());
                 },
-                ChildCommand::Lua(c) => {
+                ChildCommand::Lua(c) | ChildCommand::Subshell(c) | ChildCommand::Builtin(c, _) => {
                     if let Some(prev_stdout) = prev_stdout {
                         c.stdin[PIPE_READ] = prev_stdout;
                     }
@@ -586,15 +1093,13 @@ impl<'a> CliParser<'a> {
                     // The repeat circumvents moving issues
                     match child {
                         ChildProcess::Bash(mut child) => {
-                            // Extra steps are performed to avoid premature closing of pipes
-                            let stdout = child.stdout.take().unwrap();
-                            let stdout_fd = stdout.as_raw_fd();
-                            child.stdout = Some(stdout);
+                            // The next command takes ownership of the pipe
+                            let stdout_fd = child.stdout.take().unwrap().into_raw_fd();
                             children.push(child.id() as i32);
                             prev_stdout = Some(stdout_fd);
                             self.children.insert(child.id() as i32, child);
                         },
-                        ChildProcess::Lua(child) => {
+                        ChildProcess::Lua(child) | ChildProcess::Subshell(child) => {
                             let stdout = child.stdout[PIPE_READ];
                             children.push(child.pid);
                             prev_stdout = Some(stdout);
@@ -610,178 +1115,1489 @@ impl<'a> CliParser<'a> {
         Ok((children, prev_stdout))
     }
 
-    fn cd(self: &mut Self, command: &Command) {
-        let mut dir: String = "~".to_string();
-        if command.len() > 1 {
-            dir = command[1].clone();
-        }
-        if dir == "~" {
-            match home::home_dir() {
-                Some(p) => dir = p.display().to_string(),
-                None => println!("Home directory not found")
-            };
-        } else {
-            dir = dir.replace("~", &home::home_dir().unwrap().display().to_string());
+    /// `cd [dir | -]`, searching `$CDPATH` for relative directories.
+    fn cd(self: &mut Self, command: &Command, streams: &mut Streams) -> i32 {
+        let home_dir = home::home_dir().map(|p| p.display().to_string()).unwrap_or_default();
+
+        let (dir, print) = match command.get(1).map(String::as_str) {
+            None | Some("~") => (home_dir.clone(), false),
+            Some("-") => match env::var(OLDPWD_KEY) {
+                Ok(dir) => (dir, true),
+                Err(_) => {
+                    _ = writeln!(streams.stderr, "luabster: cd: OLDPWD not set");
+                    return 1;
+                }
+            },
+            Some(dir) => directories::search_cdpath(&directories::expand_tilde(dir, &home_dir)),
+        };
+
+        if !self.change_dir(&dir, "cd", streams) {
+            return 1;
         }
-        if let Err(e) = std::env::set_current_dir(dir) {
-            println!("{}\r\n", e);
+        if print {
+            _ = writeln!(streams.stdout, "{}", Self::current_dir());
         }
+        0
     }
 
-    fn exit(&mut self, _command: &Command) {
+    /// `pushd [dir | +N | -N]`
+    fn pushd(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let cwd = Self::current_dir();
+
+        match command.get(1) {
+            None => {
+                if self.directories.is_empty() {
+                    _ = writeln!(streams.stderr, "luabster: pushd: no other directory");
+                    return 1;
+                }
+                // Swap the two topmost directories
+                let dir = self.directories.remove(1).unwrap();
+                if !self.change_dir(&dir, "pushd", streams) {
+                    self.directories.push(&dir);
+                    return 1;
+                }
+                self.directories.push(&cwd);
+            },
+            Some(arg) if arg.starts_with(['+', '-']) && arg.len() > 1 => {
+                let Some(n) = self.directories.parse_index(arg) else {
+                    _ = writeln!(streams.stderr, "luabster: pushd: {}: directory stack index out of range", arg);
+                    return 1;
+                };
+                let saved = self.directories.clone();
+                let dir = self.directories.rotate(n, &cwd).unwrap();
+                if !self.change_dir(&dir, "pushd", streams) {
+                    self.directories = saved;
+                    return 1;
+                }
+            },
+            Some(dir) => {
+                let (dir, _) = directories::search_cdpath(&Self::expand_home(dir));
+                if !self.change_dir(&dir, "pushd", streams) {
+                    return 1;
+                }
+                self.directories.push(&cwd);
+            },
+        }
 
+        self.print_dirs(false, false, streams);
+        0
     }
 
-    fn alias(&mut self, command: &Command) {
-        if command.len() != 2 {
-            return;
+    /// `popd [+N | -N]`
+    fn popd(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        if self.directories.is_empty() {
+            _ = writeln!(streams.stderr, "luabster: popd: directory stack empty");
+            return 1;
         }
-        if let Some(idx) = command[1].find('=') {
-            let (name, cmd) = command[1].split_at(idx);
-            self.aliases.insert(name.to_string(), cmd[1..].to_string());
+
+        let n = match command.get(1) {
+            None => 0,
+            Some(arg) => match self.directories.parse_index(arg) {
+                Some(n) => n,
+                None => {
+                    _ = writeln!(streams.stderr, "luabster: popd: {}: invalid argument", arg);
+                    return 1;
+                }
+            },
+        };
+
+        if n == 0 {
+            let dir = self.directories.entries("")[1].clone();
+            if !self.change_dir(&dir, "popd", streams) {
+                return 1;
+            }
+            self.directories.remove(1);
+        } else {
+            self.directories.remove(n);
         }
+
+        self.print_dirs(false, false, streams);
+        0
     }
 
-    fn source(&mut self, command: &Command) {
-        for cmd in &command[1..] {
-            match std::fs::read_to_string(cmd) {
-                Ok(s) => {
-                    for line in s.lines() {
-                        _ = self.parse_inputs(line);
+    /// `dirs [-c] [-l] [-p] [-v] [+N | -N]`
+    fn dirs(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let (mut clear, mut long, mut per_line, mut verbose) = (false, false, false, false);
+
+        for arg in &command[1..] {
+            match arg.as_str() {
+                "-c" => clear = true,
+                "-l" => long = true,
+                "-p" => per_line = true,
+                "-v" => verbose = true,
+                _ => match self.directories.parse_index(arg) {
+                    Some(n) => {
+                        let dir = self.directories.entries(&Self::current_dir()).swap_remove(n);
+                        _ = writeln!(streams.stdout, "{}", if long { dir } else { Self::contract_home(&dir) });
+                        return 0;
+                    },
+                    None => {
+                        _ = writeln!(streams.stderr, "luabster: dirs: {}: invalid argument", arg);
+                        return 1;
                     }
                 },
-                Err(_) => (),
             }
         }
-    }
 
-    fn export(&mut self, command: &Command) {
-        if let Some(idx) = command[1].find('=') {
-            let (var, val) = command[1].split_at(idx);
-            std::env::set_var(var, &Self::expand_string(&val[1..]));
+        if clear {
+            self.directories.clear();
+            return 0;
         }
-    }
 
-    fn expand_string(s: &str) -> String {
-        if let Ok(s) = shellexpand::env(s) {
-            if let Ok(s) = expand::expand_bash(&s) {
-                s.to_string()
-            } else {
-                s.to_string()
+        if verbose {
+            for (i, dir) in self.directories.entries(&Self::current_dir()).iter().enumerate() {
+                _ = writeln!(streams.stdout, "{:2}  {}", i, if long { dir.clone() } else { Self::contract_home(dir) });
             }
         } else {
-            s.to_string()
+            self.print_dirs(long, per_line, streams);
         }
+        0
     }
 
-    fn get_job_index(&mut self, pid: Option<u32>) -> Option<usize> {
-        if pid.is_none() {
-            if self.jobs.len() == 0 {
-                return None;
-            }
-            return Some(self.jobs.len() - 1);
-        }
-        let pid = pid.unwrap();
-        let mut index = 0;
-        for children in &self.jobs {
-            if children.iter().fold(0, |acc, child| { if *child as u32 == pid { acc + 1 } else { acc } }) > 0 {
-                return Some(index);
-            } else {
-                index += 1;
-            }
+    /// `z [-l | -x] [fragment ...]`, changing to the most frecent directory
+    /// matching all fragments.
+    fn z(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let cwd = Self::current_dir();
+
+        match command.get(1).map(String::as_str) {
+            None | Some("-l") => {
+                let fragments = command.get(2..).unwrap_or_default();
+                for entry in frecency::query(fragments).iter().rev() {
+                    _ = writeln!(streams.stdout, "{:<10.1} {}", frecency::score(entry), entry.path);
+                }
+            },
+            Some("-x") => {
+                if !frecency::remove(&cwd) {
+                    _ = writeln!(streams.stderr, "luabster: z: {}: not in database", cwd);
+                    return 1;
+                }
+            },
+            Some(dir) if command.len() == 2 && dir.contains('/') && std::path::Path::new(&Self::expand_home(dir)).is_dir() => {
+                return i32::from(!self.change_dir(&Self::expand_home(dir), "z", streams));
+            },
+            Some(_) => {
+                match frecency::query(&command[1..]).into_iter().find(|e| e.path != cwd) {
+                    Some(entry) => return i32::from(!self.change_dir(&entry.path, "z", streams)),
+                    None => {
+                        _ = writeln!(streams.stderr, "luabster: z: no match for {}", command[1..].join(" "));
+                        return 1;
+                    }
+                }
+            },
         }
-        None
+        0
     }
 
-    fn fg(&mut self, command: &Command) {
-        let pid = if command.len() == 1 { None } else { command[1].parse().ok() };
-        if let Some(job_index) = self.get_job_index(pid) {
-            self.cur_job = Some(job_index);
-            unsafe {
-                self.kill(sig_CONT);
-            }
-            self.wait_for_children_to_finish();
-        }
+    fn print_dirs(&self, long: bool, per_line: bool, streams: &mut Streams) {
+        let entries: Vec<String> = self.directories.entries(&Self::current_dir())
+            .iter()
+            .map(|d| if long { d.clone() } else { Self::contract_home(d) })
+            .collect();
+
+        _ = writeln!(streams.stdout, "{}", entries.join(if per_line { "\n" } else { " " }));
     }
 
-    fn bg(&mut self, command: &Command) {
-        let pid = if command.len() == 1 { None } else { command[1].parse().ok() };
-        if let Some(job_index) = self.get_job_index(pid) {
-            self.cur_job = None;
-            unsafe {
-                self.kill_job(job_index, sig_CONT);
-            }
-        }
+    fn expand_home(dir: &str) -> String {
+        let home_dir = home::home_dir().map(|p| p.display().to_string()).unwrap_or_default();
+        directories::expand_tilde(dir, &home_dir)
     }
 
-    fn eval(&mut self, command: &Command) {
-        for cmd in &command[1..] {
-            self.parse_input(cmd);
-        }
+    fn contract_home(dir: &str) -> String {
+        let home_dir = home::home_dir().map(|p| p.display().to_string()).unwrap_or_default();
+        directories::contract_tilde(dir, &home_dir)
     }
 
-    fn update_config(&mut self, _: &Command) {
-        self.configure();
-        _ = self.lua_parser.load_scripts();
+    fn current_dir() -> String {
+        env::current_dir().map(|d| d.display().to_string()).unwrap_or_default()
     }
 
+    /// Changes the working directory, keeping `PWD`/`OLDPWD` and the frecency
+    /// database up to date and running the `chpwd` hooks. Errors are reported
+    /// as coming from `builtin`.
+    fn change_dir(&mut self, dir: &str, builtin: &str, streams: &mut Streams) -> bool {
+        let old_dir = Self::current_dir();
 
-    fn is_builtin(command: &Command) -> bool {
-        Self::BUILTIN_COMMANDS.map(|(n,_)| n).contains(&command[0].as_str())
-    }
+        if let Err(e) = env::set_current_dir(dir) {
+            _ = writeln!(streams.stderr, "luabster: {}: {}: {}", builtin, dir, e);
+            return false;
+        }
 
+        let new_dir = Self::current_dir();
+        env::set_var(OLDPWD_KEY, &old_dir);
+        env::set_var(PWD_KEY, &new_dir);
+        frecency::add(&new_dir);
+        self.run_hooks("chpwd", (new_dir, old_dir));
+        true
+    }
 
-    fn check_builtin_command(&mut self, command: &Command) -> bool {
-        let is_builtin = Self::is_builtin(command);
+    /// `exit [n]`, leaving the shell with `n` or the last command's status.
+    fn exit(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let status = command.get(1).and_then(|n| n.parse().ok()).unwrap_or(self.last_status);
+        _ = streams.stdout.flush();
+        self.shut_down(status)
+    }
 
-        if is_builtin {
-            (self.builtin_handlers.get(&command[0] as &str).unwrap())(self, command);
+    /// `alias [name[=body] ...]`, defining aliases or printing them.
+    fn alias(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        if command.len() == 1 {
+            for (name, body) in self.get_aliases() {
+                _ = writeln!(streams.stdout, "alias {}={}", name, quote_alias(&body));
+            }
+            return 0;
         }
-         
-        is_builtin
-    }
 
-    fn execute_command(&mut self, command: &mut ChildCommand) -> Result<ChildProcess, std::io::Error> {
-        log!(LogLevel::Debug, "Executing: {:?}", command);
-        
-        match command {
-            ChildCommand::Bash(command) => {
-                match command.spawn() {
-                    Ok(p) => Ok(ChildProcess::Bash(p)),
-                    Err(e) => {
-                        println!("{:?}", e);
-                        Err(e)
+        let mut status = 0;
+        for arg in &command[1..] {
+            match arg.split_once('=') {
+                Some((name, body)) => self.set_alias(name, body),
+                None => match self.get_alias(arg) {
+                    Some(body) => _ = writeln!(streams.stdout, "alias {}={}", arg, quote_alias(&body)),
+                    None => {
+                        _ = writeln!(streams.stderr, "luabster: alias: {}: not found", arg);
+                        status = 1;
                     }
                 }
-            },
-            ChildCommand::Lua(command)  => {
-                unsafe {
-                    Ok(ChildProcess::Lua(lua_runner_run_command(&mut self.lua_parser as *mut lua_parser::LuaParser as *mut std::ffi::c_void, command as *mut Child)))
-                }
             }
         }
+        status
     }
 
-    fn wait_for_children_to_finish(&mut self) {
-        while unsafe { std::ptr::read_volatile(&self.should_wait) } {
-            if !self.get_current_job().is_some_and(|j| !j.is_empty()) {
-                break;
+    /// `unalias [-a] [name ...]`
+    fn unalias(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut status = 0;
+        for name in &command[1..] {
+            if name == "-a" {
+                self.aliases.clear();
+            } else if !self.remove_alias(name) {
+                _ = writeln!(streams.stderr, "luabster: unalias: {}: not found", name);
+                status = 1;
             }
         }
+        status
     }
 
-    fn try_wait(pid: u32) -> Result<Option<i32>, ()> {
-        unsafe {
-            let status = try_wait_process(pid);
-            if status == PROCESS_RUNNING {
-                    Ok(None)
-            } else if status == PROCESS_STOPPED {
-                Ok(Some(PROCESS_STOPPED))
-            } else {
-                Err(())
-            }
-        }
-    }
+    /// `abbr [name [expansion ...]]` or `abbr -e name ...`, managing abbreviations
+    /// that are expanded while typing.
+    fn abbr(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut status = 0;
+
+        match command.get(1).map(String::as_str) {
+            None => {
+                for (name, expansion) in self.input_parser.get_abbreviations() {
+                    _ = writeln!(streams.stdout, "abbr {} {}", name, quote_alias(&expansion));
+                }
+            },
+            Some("-e" | "--erase") => {
+                for name in &command[2..] {
+                    if !self.input_parser.remove_abbreviation(name) {
+                        _ = writeln!(streams.stderr, "luabster: abbr: {}: not found", name);
+                        status = 1;
+                    }
+                }
+            },
+            Some(name) if command.len() == 2 => match self.input_parser.get_abbreviation(name) {
+                Some(expansion) => _ = writeln!(streams.stdout, "abbr {} {}", name, quote_alias(&expansion)),
+                None => {
+                    _ = writeln!(streams.stderr, "luabster: abbr: {}: not found", name);
+                    status = 1;
+                }
+            },
+            Some(name) => self.input_parser.set_abbreviation(name, &command[2..].join(" ")),
+        }
+        status
+    }
+
+    pub fn get_alias(&self, name: &str) -> Option<String> {
+        self.aliases.get(name).cloned()
+    }
+
+    /// Returns all aliases sorted by name.
+    pub fn get_aliases(&self) -> Vec<(String, String)> {
+        self.aliases.iter().map(|(n, b)| (n.to_string(), b.to_string())).sorted().collect()
+    }
+
+    pub fn set_alias(&mut self, name: &str, body: &str) {
+        self.aliases.insert(name.to_string(), body.to_string());
+    }
+
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    fn source(&mut self, command: &Command, _: &mut Streams) -> i32 {
+        for cmd in &command[1..] {
+            self.source_file(cmd);
+        }
+        self.last_status
+    }
+
+    pub fn source_file(&mut self, file: &str) {
+        match std::fs::read_to_string(file) {
+            Ok(s) => {
+                for line in s.lines() {
+                    _ = self.parse_inputs(line);
+                }
+            },
+            Err(_) => (),
+        }
+    }
+
+    fn export(&mut self, command: &Command, _: &mut Streams) -> i32 {
+        if let Some(idx) = command[1].find('=') {
+            let (var, val) = command[1].split_at(idx);
+            std::env::set_var(var, &self.expand_string(&val[1..]));
+            self.variables.remove(var);
+
+            if var == "PATH" {
+                command_hash::rehash();
+            }
+        } else if let Some(val) = command.get(1).and_then(|var| self.variables.remove(var)) {
+            env::set_var(&command[1], val);
+        }
+        0
+    }
+
+    fn hash(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        if command.len() == 1 {
+            let remembered = command_hash::get_remembered();
+            if remembered.is_empty() {
+                _ = writeln!(streams.stdout, "hash: hash table empty");
+            } else {
+                _ = writeln!(streams.stdout, "hits\tcommand");
+                for (_, path, hits) in remembered {
+                    _ = writeln!(streams.stdout, "{:4}\t{}", hits, path);
+                }
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for name in &command[1..] {
+            if name == "-r" {
+                command_hash::rehash();
+            } else if command_hash::remember(name).is_none() {
+                _ = writeln!(streams.stderr, "luabster: hash: {}: not found", name);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// Resolves `name` the way a command would be, in order of precedence.
+    pub fn resolve(&self, name: &str) -> Option<Resolution> {
+        if let Some(alias) = self.aliases.get(name) {
+            Some(Resolution::Alias(alias.to_string()))
+        } else if Self::is_builtin_name(name) {
+            Some(Resolution::Builtin)
+        } else if name.contains('/') {
+            std::path::Path::new(name).exists().then(|| Resolution::Executable(name.to_string()))
+        } else if let Some(path) = command_hash::lookup(name) {
+            Some(Resolution::Executable(path))
+        } else if self.lua_parser.is_function(name) {
+            Some(Resolution::LuaFunction)
+        } else {
+            None
+        }
+    }
+
+    fn describe(&self, name: &str) -> Option<String> {
+        let description = match self.resolve(name)? {
+            Resolution::Alias(alias) => format!("{} is aliased to `{}'", name, alias),
+            Resolution::Builtin => format!("{} is a shell builtin", name),
+            Resolution::Executable(path) if command_hash::is_remembered(name) => format!("{} is hashed ({})", name, path),
+            Resolution::Executable(path) => format!("{} is {}", name, path),
+            Resolution::LuaFunction => format!("{} is a Lua function", name),
+        };
+
+        Some(description)
+    }
+
+
+    fn type_of(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let terse = command.get(1).is_some_and(|a| a == "-t");
+        let names = if terse { &command[2..] } else { &command[1..] };
+
+        let mut status = 0;
+        for name in names {
+            let description = if terse {
+                self.resolve(name).map(|r| match r {
+                    Resolution::Alias(_) => "alias".to_string(),
+                    Resolution::Builtin => "builtin".to_string(),
+                    Resolution::Executable(_) => "file".to_string(),
+                    Resolution::LuaFunction => "function".to_string(),
+                })
+            } else {
+                self.describe(name)
+            };
+
+            match description {
+                Some(d) => _ = writeln!(streams.stdout, "{}", d),
+                None => {
+                    if !terse {
+                        _ = writeln!(streams.stderr, "luabster: type: {}: not found", name);
+                    }
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    fn which(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut status = 0;
+        for name in &command[1..] {
+            _ = match self.resolve(name) {
+                Some(Resolution::Alias(alias)) => writeln!(streams.stdout, "{}: aliased to {}", name, alias),
+                Some(Resolution::Builtin) => writeln!(streams.stdout, "{}: shell built-in command", name),
+                Some(Resolution::Executable(path)) => writeln!(streams.stdout, "{}", path),
+                Some(Resolution::LuaFunction) => writeln!(streams.stdout, "{}: Lua function", name),
+                None => {
+                    status = 1;
+                    writeln!(streams.stderr, "{} not found", name)
+                }
+            };
+        }
+        status
+    }
+
+    /// `command [-v|-V] name [args]`, running `name` while bypassing aliases and Lua functions.
+    fn command(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        match command.get(1).map(String::as_str) {
+            None => 0,
+            Some(flag @ ("-v" | "-V")) => {
+                let mut status = 0;
+                for name in &command[2..] {
+                    let description = if flag == "-V" {
+                        self.describe(name)
+                    } else {
+                        self.resolve(name).map(|r| match r {
+                            Resolution::Alias(alias) => format!("alias {}='{}'", name, alias),
+                            Resolution::Executable(path) => path,
+                            Resolution::Builtin | Resolution::LuaFunction => name.to_string(),
+                        })
+                    };
+
+                    match description {
+                        Some(d) => _ = writeln!(streams.stdout, "{}", d),
+                        None => {
+                            if flag == "-V" {
+                                _ = writeln!(streams.stderr, "luabster: command: {}: not found", name);
+                            }
+                            status = 1;
+                        }
+                    }
+                }
+                status
+            },
+            Some(_) => {
+                // Anything written so far must come before the command's output
+                _ = streams.stdout.flush();
+                self.should_wait = true;
+                if let Err(Errors::NoProgramFound(p)) = self.run_commands(vec![command[1..].to_vec()], &mut None, false) {
+                    _ = writeln!(streams.stderr, "luabster: {}: command not found", p);
+                }
+                self.last_status
+            }
+        }
+    }
+
+    /// `builtin name [args]`, running the builtin `name` even if shadowed by an alias.
+    fn builtin(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let Some(name) = command.get(1) else {
+            return 0;
+        };
+
+        if Self::is_builtin_name(name) {
+            self.run_builtin(&command[1..].to_vec(), streams)
+        } else {
+            _ = writeln!(streams.stderr, "luabster: builtin: {}: not a shell builtin", name);
+            1
+        }
+    }
+
+    /// `set [-eux | +eux] [-C | +C] [-o name | +o name] [-- arg ...]`. A bare
+    /// `-o` or `+o` lists the options, `--` sets the positional parameters.
+    fn set(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        if command.len() == 1 {
+            self.print_options(true, streams);
+            return 0;
+        }
+
+        let mut status = 0;
+        let mut args = command[1..].iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                self.set_positional(args.cloned().collect());
+                break;
+            }
+
+            let value = arg.starts_with('-');
+            if !value && !arg.starts_with('+') || arg.len() < 2 {
+                _ = writeln!(streams.stderr, "luabster: set: {}: invalid option", arg);
+                return 2;
+            }
+
+            if &arg[1..] == "o" {
+                match args.next() {
+                    Some(name) => if !self.options.set(name, value) {
+                        _ = writeln!(streams.stderr, "luabster: set: {}: invalid option name", name);
+                        status = 2;
+                    },
+                    None => self.print_options(value, streams),
+                }
+                continue;
+            }
+
+            for flag in arg[1..].chars() {
+                match options::ShellOptions::name_of_flag(flag) {
+                    Some(name) => _ = self.options.set(name, value),
+                    None => {
+                        _ = writeln!(streams.stderr, "luabster: set: {}{}: invalid option", &arg[..1], flag);
+                        status = 2;
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    /// `trap [-lp] [[command | -] condition ...]`
+    fn trap(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        match command.get(1).map(String::as_str) {
+            None | Some("-p") => {
+                let mut conditions: Vec<&String> = self.traps.keys().collect();
+                conditions.sort();
+                for name in conditions {
+                    _ = writeln!(streams.stdout, "trap -- {} {}", quote_alias(&self.traps[name]), name);
+                }
+                for name in self.lua_parser.get_traps() {
+                    _ = writeln!(streams.stdout, "trap -- <lua function> {}", name);
+                }
+                return 0;
+            },
+            Some("-l") => {
+                for (sig, name) in traps::signals() {
+                    _ = writeln!(streams.stdout, "{:2}) SIG{}", sig, name);
+                }
+                return 0;
+            },
+            _ => (),
+        }
+
+        // A lone condition, or `-` as command, resets the conditions
+        let (handler, conditions) = if command.len() == 2 || command[1] == "-" {
+            (None, if command.len() == 2 { &command[1..] } else { &command[2..] })
+        } else {
+            (Some(command[1].as_str()), &command[2..])
+        };
+
+        let mut status = 0;
+        for condition in conditions {
+            match traps::condition_name(condition) {
+                Some(name) => self.set_trap(&name, handler),
+                None => {
+                    _ = writeln!(streams.stderr, "luabster: trap: {}: invalid signal specification", condition);
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// `echo [-neE] [arg ...]`
+    fn echo(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut newline = true;
+        let mut escapes = false;
+        let mut args = &command[1..];
+
+        // Only words made of known flags are options, anything else is echoed
+        while let Some(flags) = args.first().and_then(|a| a.strip_prefix('-')).filter(|f| !f.is_empty() && f.chars().all(|c| "neE".contains(c))) {
+            for flag in flags.chars() {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            args = &args[1..];
+        }
+
+        let mut output = args.join(" ");
+        if escapes {
+            let (unescaped, stop) = printf::unescape(&output);
+            output = unescaped;
+            newline &= !stop;
+        }
+        if newline {
+            output.push('\n');
+        }
+
+        write_output(&mut *streams.stdout, &output)
+    }
+
+    /// `printf [-v var] format [arg ...]`, assigning the output to `var` if given.
+    fn printf(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let (var, args) = match command.get(1).map(String::as_str) {
+            Some("-v") => (command.get(2), command.get(3..).unwrap_or_default()),
+            _ => (None, &command[1..]),
+        };
+        let Some((format, args)) = args.split_first() else {
+            _ = writeln!(streams.stderr, "luabster: printf: usage: printf [-v var] format [arguments]");
+            return 2;
+        };
+
+        let (output, errors) = printf::format(format, args);
+        for error in &errors {
+            _ = writeln!(streams.stderr, "luabster: printf: {}", error);
+        }
+
+        let status = match var {
+            Some(var) => {
+                self.set_variable(var, &output);
+                0
+            },
+            None => write_output(&mut *streams.stdout, &output),
+        };
+        if errors.is_empty() { status } else { 1 }
+    }
+
+    /// `test expr` and `[ expr ]`
+    fn test(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut args = &command[1..];
+        if command[0] == "[" {
+            match args.split_last() {
+                Some((last, rest)) if last == "]" => args = rest,
+                _ => {
+                    _ = writeln!(streams.stderr, "luabster: [: missing `]'");
+                    return 2;
+                }
+            }
+        }
+
+        match condition::test(args) {
+            Ok(result) => i32::from(!result),
+            Err(e) => {
+                _ = writeln!(streams.stderr, "luabster: {}: {}", command[0], e);
+                2
+            }
+        }
+    }
+
+    fn always_true(&mut self, _: &Command, _: &mut Streams) -> i32 {
+        0
+    }
+
+    fn always_false(&mut self, _: &Command, _: &mut Streams) -> i32 {
+        1
+    }
+
+    /// `read [-rs] [-p prompt] [-d delim] [name ...]`, splitting a line of stdin
+    /// on `$IFS` into the variables, or assigning all of it to `$REPLY`. Unless
+    /// `-r` is given, a backslash escapes the next character. With `-s`, a line
+    /// from the terminal is read with the line editor without echoing it.
+    fn read(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let mut raw = false;
+        let mut hidden = false;
+        let mut prompt = None;
+        let mut delimiter = b'\n';
+        let mut names = Vec::new();
+
+        let mut args = command[1..].iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                flags if flags.len() > 1 && flags.starts_with('-') && flags[1..].chars().all(|c| "rs".contains(c)) => {
+                    raw |= flags.contains('r');
+                    hidden |= flags.contains('s');
+                },
+                "-p" | "-d" => {
+                    let Some(value) = args.next() else {
+                        _ = writeln!(streams.stderr, "luabster: read: {}: option requires an argument", arg);
+                        return 2;
+                    };
+                    if arg == "-d" {
+                        delimiter = value.bytes().next().unwrap_or(0);
+                    } else {
+                        prompt = Some(value.as_str());
+                    }
+                },
+                _ if arg.len() > 1 && arg.starts_with('-') => {
+                    _ = writeln!(streams.stderr, "luabster: read: {}: invalid option", arg);
+                    return 2;
+                },
+                _ => names.push(arg.as_str()),
+            }
+        }
+
+        let terminal = io::IsTerminal::is_terminal(&io::stdin());
+        if hidden && terminal {
+            _ = streams.stderr.flush();
+            return match termio::prompt_for_input(prompt.unwrap_or(""), None, true) {
+                Ok(line) => {
+                    self.assign_read(&line, &names);
+                    0
+                },
+                Err(_) => 1,
+            };
+        }
+        if let Some(prompt) = prompt.filter(|_| terminal) {
+            _ = write!(streams.stderr, "{}", prompt);
+        }
+
+        // stdin is unbuffered, reading byte by byte leaves the rest to the following commands
+        let mut line = Vec::new();
+        let mut byte = [0];
+        let mut escaped = false;
+        let mut complete = false;
+        loop {
+            match streams.stdin.read(&mut byte) {
+                Ok(1) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                _ => break,
+            }
+
+            if escaped {
+                escaped = false;
+                if byte[0] != b'\n' {
+                    line.push(byte[0]);
+                }
+            } else if !raw && byte[0] == b'\\' {
+                escaped = true;
+            } else if byte[0] == delimiter {
+                complete = true;
+                break;
+            } else {
+                line.push(byte[0]);
+            }
+        }
+
+        self.assign_read(&String::from_utf8_lossy(&line), &names);
+
+        i32::from(!complete)
+    }
+
+    fn assign_read(&mut self, line: &str, names: &[&str]) {
+        if names.is_empty() {
+            self.set_variable(REPLY_KEY, line);
+        } else {
+            let ifs = self.variable(IFS_KEY).unwrap_or(DEFAULT_IFS.to_string());
+            let fields = split_fields(line, &ifs, names.len());
+            for (i, name) in names.iter().enumerate() {
+                self.set_variable(name, fields.get(i).map_or("", String::as_str));
+            }
+        }
+    }
+
+    /// `shift [n]`, dropping the first `n` positional parameters.
+    fn shift(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let n = match command.get(1).map(|n| n.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) => n,
+            Some(Err(_)) => {
+                _ = writeln!(streams.stderr, "luabster: shift: {}: numeric argument required", command[1]);
+                return 2;
+            }
+        };
+
+        if n > self.positional.len() {
+            return 1;
+        }
+
+        self.set_positional(self.positional[n..].to_vec());
+        0
+    }
+
+    /// Sets the positional parameters `$1`, `$2`, ... and with them `$#` and `$@`,
+    /// see `variable`.
+    pub fn set_positional(&mut self, args: Vec<String>) {
+        self.positional = args;
+    }
+
+    /// The value of the parameter `name`: a positional parameter, `$#`, `$@`, the
+    /// match of the last `=~`, a shell variable or an environment variable.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "0" => Some("luabster".to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+                self.positional.get(name.parse::<usize>().ok()?.checked_sub(1)?).cloned()
+            },
+            REMATCH_KEY => self.rematch.first().cloned(),
+            _ => match name.strip_prefix(REMATCH_KEY).and_then(|i| i.strip_prefix('_')) {
+                Some(i) => self.rematch.get(i.parse::<usize>().ok()?).cloned(),
+                None => self.variables.get(name).cloned().or_else(|| env::var(name).ok()),
+            },
+        }
+    }
+
+    /// Assigns `value` to the shell variable `name`, or to the environment variable
+    /// if `name` was exported.
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        if env::var_os(name).is_some() {
+            env::set_var(name, value);
+        } else {
+            self.variables.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// `break [n]`, leaving the `n` innermost `select` loops.
+    fn break_loop(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let n = match command.get(1).map(|n| n.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                _ = writeln!(streams.stderr, "luabster: break: {}: loop count out of range", command[1]);
+                return 1;
+            }
+        };
+
+        if self.loop_depth == 0 {
+            _ = writeln!(streams.stderr, "luabster: break: only meaningful in a `select' loop");
+            return 0;
+        }
+        self.loop_break = n.min(self.loop_depth);
+        0
+    }
+
+    /// `exec [command [arg ...]]`, replacing the shell with `command`.
+    fn exec(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        if command.len() == 1 {
+            return 0;
+        }
+
+        _ = streams.stdout.flush();
+        _ = std::io::Write::flush(&mut std::io::stdout());
+        let error = std::os::unix::process::CommandExt::exec(&mut Self::spawn_command(&command[1..].to_vec()));
+
+        _ = writeln!(streams.stderr, "luabster: exec: {}: {}", command[1], error);
+        if error.kind() == io::ErrorKind::NotFound { 127 } else { 126 }
+    }
+
+    /// `umask [-S] [mode]`, with `mode` in octal or symbolic like `chmod`.
+    fn umask(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let symbolic = command.get(1).is_some_and(|a| a == "-S");
+
+        match command.get(if symbolic { 2 } else { 1 }) {
+            None if symbolic => _ = writeln!(streams.stdout, "{}", limits::symbolic_umask(limits::umask())),
+            None => _ = writeln!(streams.stdout, "{:04o}", limits::umask()),
+            Some(mode) => match limits::parse_umask(mode, limits::umask()) {
+                Some(mask) => limits::set_umask(mask),
+                None => {
+                    _ = writeln!(streams.stderr, "luabster: umask: {}: invalid mode", mode);
+                    return 1;
+                }
+            },
+        }
+        0
+    }
+
+    /// `ulimit [-SH] [-a | -option ...] [limit | unlimited]`, showing or setting
+    /// the file size limit if no other is given. Setting a limit changes both
+    /// the soft and hard limits unless `-S` or `-H` is given.
+    fn ulimit(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let (mut soft, mut hard) = (false, false);
+        let mut options = Vec::new();
+        let mut value = None;
+
+        for arg in &command[1..] {
+            let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                value = Some(arg.as_str());
+                continue;
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'S' => soft = true,
+                    'H' => hard = true,
+                    'a' => options.extend(limits::LIMITS.iter().map(|(o, _, _)| *o)),
+                    _ if limits::LIMITS.iter().any(|(o, _, _)| *o == flag) => options.push(flag),
+                    _ => {
+                        _ = writeln!(streams.stderr, "luabster: ulimit: -{}: invalid option", flag);
+                        return 2;
+                    }
+                }
+            }
+        }
+        if options.is_empty() {
+            options.push('f');
+        }
+
+        let mut status = 0;
+        if let Some(value) = value {
+            let limit = match value {
+                "unlimited" => None,
+                _ => match value.parse() {
+                    Ok(limit) => Some(limit),
+                    Err(_) => {
+                        _ = writeln!(streams.stderr, "luabster: ulimit: {}: invalid number", value);
+                        return 1;
+                    }
+                },
+            };
+            let (soft, hard) = if soft || hard { (soft, hard) } else { (true, true) };
+
+            for option in options {
+                if let Err(e) = limits::set(option, soft, hard, limit) {
+                    _ = writeln!(streams.stderr, "luabster: ulimit: -{}: cannot modify limit: {}", option, e);
+                    status = 1;
+                }
+            }
+            return status;
+        }
+
+        for (option, description, _) in limits::LIMITS.iter().filter(|(o, _, _)| options.contains(o)) {
+            match limits::get(*option, hard && !soft) {
+                Ok(limit) => {
+                    let limit = limit.map_or("unlimited".to_string(), |l| l.to_string());
+                    if options.len() > 1 {
+                        _ = writeln!(streams.stdout, "{:<32}(-{}) {}", description, option, limit);
+                    } else {
+                        _ = writeln!(streams.stdout, "{}", limit);
+                    }
+                },
+                Err(e) => {
+                    _ = writeln!(streams.stderr, "luabster: ulimit: -{}: {}", option, e);
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// Lists the options as a table, or as `set` commands restoring them.
+    fn print_options(&self, table: bool, streams: &mut Streams) {
+        for (name, _) in options::OPTION_NAMES {
+            let on = self.options.get(name).unwrap_or(false);
+            if table {
+                _ = writeln!(streams.stdout, "{:<15}{}", name, if on { "on" } else { "off" });
+            } else {
+                _ = writeln!(streams.stdout, "set {}o {}", if on { '-' } else { '+' }, name);
+            }
+        }
+    }
+
+    fn expand_string(&self, s: &str) -> String {
+        let s = expand::expand_variables(s, &|name| Some(self.variable(name).unwrap_or_default()))
+            .unwrap_or(s.to_string());
+        expand::expand_bash(&s).unwrap_or(s)
+    }
+
+    fn get_job_index(&mut self, pid: Option<u32>) -> Option<usize> {
+        if pid.is_none() {
+            if self.jobs.len() == 0 {
+                return None;
+            }
+            return Some(self.jobs.len() - 1);
+        }
+        let pid = pid.unwrap();
+        let mut index = 0;
+        for children in &self.jobs {
+            if children.iter().fold(0, |acc, child| { if *child as u32 == pid { acc + 1 } else { acc } }) > 0 {
+                return Some(index);
+            } else {
+                index += 1;
+            }
+        }
+        None
+    }
+
+    fn fg(&mut self, command: &Command, _: &mut Streams) -> i32 {
+        let pid = if command.len() == 1 { None } else { command[1].parse().ok() };
+        let Some(job_index) = self.get_job_index(pid) else {
+            return 1;
+        };
+
+        let pids = self.jobs[job_index].clone();
+        self.cur_job = Some(job_index);
+        unsafe {
+            self.kill(sig_CONT);
+        }
+        self.wait_for_children_to_finish();
+        self.job_status(&pids)
+    }
+
+    fn bg(&mut self, command: &Command, _: &mut Streams) -> i32 {
+        let pid = if command.len() == 1 { None } else { command[1].parse().ok() };
+        let Some(job_index) = self.get_job_index(pid) else {
+            return 1;
+        };
+
+        self.cur_job = None;
+        unsafe {
+            self.kill_job(job_index, sig_CONT);
+        }
+        0
+    }
+
+    /// `jobs [-p]`, listing the jobs with their processes.
+    fn jobs(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let pids_only = command.get(1).is_some_and(|a| a == "-p");
+
+        for (i, job) in self.jobs.iter().enumerate() {
+            if pids_only {
+                for pid in job {
+                    _ = writeln!(streams.stdout, "{}", pid);
+                }
+            } else {
+                _ = writeln!(streams.stdout, "[{}]  {}", i + 1, job.iter().join(" "));
+            }
+        }
+        0
+    }
+
+    /// `eval [arg ...]`, running the arguments joined by spaces as a command line.
+    fn eval(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        _ = streams.stdout.flush();
+        if let Err(Errors::NoProgramFound(program)) = self.parse_inputs(&command[1..].join(" ")) {
+            _ = writeln!(streams.stderr, "luabster: {}: command not found", program);
+        }
+        self.last_status
+    }
+
+    fn update_config(&mut self, _: &Command, _: &mut Streams) -> i32 {
+        self.configure();
+        _ = self.lua_parser.load_scripts();
+        0
+    }
+
+
+    fn is_builtin(command: &Command) -> bool {
+        Self::is_builtin_name(&command[0])
+    }
+
+    /// Whether `command` is a lone directory that should be changed into.
+    fn is_auto_cd(&self, command: &Command) -> bool {
+        if !self.directories.auto_cd || command.len() != 1 {
+            return false;
+        }
+
+        let name = &command[0];
+
+        std::path::Path::new(&Self::expand_home(name)).is_dir()
+            && !Self::is_builtin_name(name)
+            && command_hash::lookup(name).is_none()
+            && !self.lua_parser.is_function(name)
+    }
+
+    fn is_builtin_name(name: &str) -> bool {
+        Self::BUILTIN_COMMANDS.map(|(n,_)| n).contains(&name)
+    }
+
+
+    fn run_builtin(&mut self, command: &Command, streams: &mut Streams) -> i32 {
+        let handler = self.builtin_handlers[command[0].as_str()].clone();
+        handler.run(self, command, streams)
+    }
+
+    /// Runs a builtin in the shell itself, with `stdin` and `output`, if any,
+    /// in place of its stdin and stdout. Closes `stdin`.
+    fn run_builtin_in_process(&mut self, command: &Command, stdin: Option<RawFd>, output: &mut Option<Box<dyn Output>>) -> i32 {
+        let saved_stdin = stdin.map(|fd| unsafe { redirect_stdin(fd) });
+
+        let status = self.with_output(output, |p| {
+            let mut streams = Streams::standard();
+            let status = p.run_builtin(command, &mut streams);
+            match streams.stdout.flush() {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                    _ = writeln!(streams.stderr, "luabster: {}: write error: {}", command[0], e);
+                    1
+                },
+                _ => status,
+            }
+        });
+
+        if let Some(saved_stdin) = saved_stdin {
+            unsafe { restore_stdin(saved_stdin); }
+        }
+        status
+    }
+
+    fn execute_command(&mut self, command: &mut ChildCommand) -> Result<ChildProcess, std::io::Error> {
+        log!(LogLevel::Debug, "Executing: {:?}", command);
+        
+        match command {
+            ChildCommand::Bash(command) => {
+                match command.spawn() {
+                    Ok(p) => Ok(ChildProcess::Bash(p)),
+                    Err(e) => {
+                        println!("{:?}", e);
+                        Err(e)
+                    }
+                }
+            },
+            ChildCommand::Lua(command)  => {
+                unsafe {
+                    Ok(ChildProcess::Lua(lua_runner_run_command(&mut self.lua_parser as *mut lua_parser::LuaParser as *mut std::ffi::c_void, command as *mut Child)))
+                }
+            },
+            ChildCommand::Subshell(command) => {
+                unsafe {
+                    Ok(ChildProcess::Subshell(subshell_run_command(self as *mut CliParser as *mut std::ffi::c_void, command as *mut Child)))
+                }
+            },
+            ChildCommand::Builtin(child, command) => {
+                // Picked up by `run_subshell` in the child
+                self.forked_builtin = Some(command.clone());
+                let child = unsafe { subshell_run_command(self as *mut CliParser as *mut std::ffi::c_void, child as *mut Child) };
+                self.forked_builtin = None;
+                Ok(ChildProcess::Subshell(child))
+            }
+        }
+    }
+
+    /// Runs the body of a `{ ...; }` group in the current shell, with `output`
+    /// as stdout of all its commands.
+    fn run_group(&mut self, body: &str, output: &mut Option<Box<dyn Output>>) -> Result<(), Errors> {
+        self.with_output(output, |p| p.parse_inputs(body))
+    }
+
+    /// Runs `f` in the current shell with `output`, if any, as stdout.
+    fn with_output<T>(&mut self, output: &mut Option<Box<dyn Output>>, f: impl FnOnce(&mut Self) -> T) -> T {
+        let saved_stdout = output.as_mut().map(|o| unsafe { redirect_stdout(o.to_fd()) });
+
+        let res = f(self);
+
+        if let Some(saved_stdout) = saved_stdout {
+            _ = std::io::Write::flush(&mut std::io::stdout());
+            unsafe { restore_stdout(saved_stdout); }
+        }
+
+        res
+    }
+
+    /// Replaces each `<(...)` and `>(...)` in `command` with the `/dev/fd/N` path
+    /// of a pipe to or from the commands within, which run in a subshell.
+    fn substitute_processes(&mut self, command: &str) -> String {
+        let mut command = command.to_string();
+
+        while let Some((start, end, is_input)) = find_process_substitution(&command) {
+            let body = &command[start + 2..end - 1];
+            let child = unsafe {
+                let mut child = subshell_spawn_substitution(body.as_ptr(), body.len() as u32, is_input.into());
+
+                // It mustn't be reaped before it is known
+                enter_critical_section();
+                let child = subshell_run_command(self as *mut CliParser as *mut std::ffi::c_void, &mut child);
+                self.substitution_statuses.insert(child.pid, None);
+                exit_critical_section();
+                child
+            };
+
+            let fd = if is_input { child.stdout[PIPE_READ] } else { child.stdin[PIPE_WRITE] };
+            self.substitution_fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            command.replace_range(start..end, &format!("/dev/fd/{}", fd));
+        }
+
+        command
+    }
+
+    /// Waits for the process substitutions of the last command, so that e.g. the
+    /// output of `>(wc -l)` isn't printed after the next prompt.
+    fn wait_substitutions(&mut self) {
+        // The statuses are filled in by the SIGCHLD handler
+        unsafe { enter_critical_section(); }
+        while self.substitution_statuses.values().any(Option::is_none) {
+            unsafe { wait_for_signal(); }
+        }
+        self.substitution_statuses.clear();
+        unsafe { exit_critical_section(); }
+    }
+
+    /// `coproc [NAME] command`, where a `NAME` must be followed by a subshell or
+    /// group. Sets the shell variables `NAME_READ` and `NAME_WRITE` to the file
+    /// descriptors to read from and write to the coprocess, and `NAME_PID` to its
+    /// pid. Commands reach the pipes through their paths, as in
+    /// `echo hi > /dev/fd/$COPROC_WRITE` or `head -n 1 /dev/fd/$COPROC_READ`.
+    fn coproc(&mut self, command: &str) {
+        let command = command.trim();
+        let (name, body) = match command.split_once(char::is_whitespace) {
+            Some((name, rest)) if split_group(rest).is_some() => (name, rest.trim()),
+            _ => (coproc::DEFAULT_NAME, command),
+        };
+
+        if body.is_empty() {
+            eprintln!("luabster: coproc: command expected");
+            self.last_status = 2;
+            return;
+        }
+
+        let coprocess = self.spawn_coprocess(body);
+        self.set_variable(&format!("{}_PID", name), &coprocess.pid.to_string());
+        self.set_variable(&format!("{}_READ", name), &coprocess.read_fd().to_string());
+        self.set_variable(&format!("{}_WRITE", name), &coprocess.write_fd().unwrap_or(-1).to_string());
+
+        // Replacing a coprocess closes our ends of its pipes
+        self.coprocesses.insert(name.to_string(), coprocess);
+        self.last_status = 0;
+    }
+
+    /// Evaluates `[[ expression ]]` in the shell, without running a command.
+    fn conditional(&mut self, expression: &str) {
+        let (expression, rest) = match expression.rfind("]]") {
+            Some(i) => expression.split_at(i + 2),
+            None => (expression, ""),
+        };
+        let Some(expression) = expression.strip_suffix("]]").filter(|e| e.is_empty() || e.ends_with(char::is_whitespace)) else {
+            eprintln!("luabster: syntax error: `]]' expected");
+            self.last_status = 2;
+            return;
+        };
+
+        // Nothing is written to an output redirection, but its file is still created
+        let rest = rest.trim();
+        if rest.starts_with('>') {
+            if Self::create_output(rest, &mut self.lua_parser, self.options.noclobber).is_err() {
+                self.last_status = 1;
+                return;
+            }
+        } else if !rest.is_empty() {
+            eprintln!("luabster: syntax error near '{}'", rest);
+            self.last_status = 2;
+            return;
+        }
+
+        let expand = |s: &str| {
+            let s = shellexpand::tilde_with_context(s, || env::var("HOME").ok());
+            let s = expand::expand_unquoted_variables(&s, &|name| Some(self.variable(name).unwrap_or_default()))
+                .unwrap_or(s.to_string());
+            expand::expand_bash(&s).unwrap_or(s)
+        };
+        let words = match condition::split_words(expression, &expand) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("luabster: [[: {}", e);
+                self.last_status = 2;
+                return;
+            }
+        };
+
+        if self.options.xtrace {
+            let ps4 = env::var(PS4_KEY).unwrap_or(DEFAULT_PS4.to_string());
+            eprintln!("{}[[ {} ]]", ps4, words.iter().map(|w| quote_word(&w.text)).join(" "));
+        }
+
+        let mut rematch = None;
+        self.last_status = match condition::conditional(&words, &mut rematch) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("luabster: [[: {}", e);
+                2
+            }
+        };
+        if let Some(rematch) = rematch {
+            self.rematch = rematch;
+        }
+    }
+
+    /// `select name [in word ...]; do commands; done`, running the commands with
+    /// `name` set to the chosen word until `break` or the menu is cancelled.
+    /// Without `in`, the positional parameters are offered.
+    fn select(&mut self, command: &str) {
+        let Some((header, body)) = split_loop(command) else {
+            eprintln!("luabster: syntax error: `do ...; done' expected");
+            self.last_status = 2;
+            return;
+        };
+        let words: Vec<String> = match Self::parse_command(&self.expand_string(header)) {
+            Ok(words) => words.iter().map(|w| expand::expand_all(w)).collect(),
+            Err(e) => {
+                eprintln!("luabster: select: {:?}", e);
+                self.last_status = 2;
+                return;
+            }
+        };
+        let (name, list) = match words.split_first() {
+            Some((name, [])) => (name, self.positional.clone()),
+            Some((name, [keyword, list @ ..])) if keyword == "in" => (name, list.to_vec()),
+            _ => {
+                eprintln!("luabster: syntax error: `select name [in word ...]' expected");
+                self.last_status = 2;
+                return;
+            }
+        };
+
+        self.last_status = 0;
+        self.loop_depth += 1;
+        let mut show_list = true;
+
+        while let Some(reply) = self.select_reply(&list, show_list) {
+            // Like `read`, an empty line shows the words again
+            show_list = reply.is_empty();
+            if show_list {
+                continue;
+            }
+            let choice = reply.parse::<usize>().ok().and_then(|n| list.get(n.wrapping_sub(1)));
+            self.set_variable(REPLY_KEY, &reply);
+            self.set_variable(name, choice.map_or("", String::as_str));
+
+            if let Err(Errors::NoProgramFound(program)) = self.parse_inputs(body) {
+                eprintln!("luabster: {}: command not found", program);
+            }
+            if self.loop_break > 0 {
+                self.loop_break -= 1;
+                break;
+            }
+        }
+
+        self.loop_depth -= 1;
+    }
+
+    /// The number of the word chosen from `list`, starting at 1, or `None` once
+    /// the menu is cancelled. On a terminal, this is a menu of the words;
+    /// otherwise the words are listed if `show_list` and a line is read.
+    fn select_reply(&mut self, list: &[String], show_list: bool) -> Option<String> {
+        let ps3 = env::var(PS3_KEY);
+
+        if io::IsTerminal::is_terminal(&io::stdin()) {
+            if let Ok(ps3) = ps3 {
+                eprintln!("{}", ps3);
+            }
+            let options: Vec<&str> = list.iter().map(String::as_str).collect();
+            return termio::get_choice(&options, 1, false).ok().map(|choice| (choice + 1).to_string());
+        }
+
+        if show_list {
+            for (i, word) in list.iter().enumerate() {
+                eprintln!("{}) {}", i + 1, word);
+            }
+        }
+        eprint!("{}", ps3.as_deref().unwrap_or(DEFAULT_PS3));
+
+        let status = self.read(&vec!["read".to_string(), "-r".to_string()], &mut Streams::standard());
+        match status {
+            0 => self.variable(REPLY_KEY),
+            _ => None,
+        }
+    }
+
+    /// The match and groups of the last `=~` test, expanded as `$BASH_REMATCH`,
+    /// `$BASH_REMATCH_1`, `$BASH_REMATCH_2`, ...
+    pub fn rematch(&self) -> &[String] {
+        &self.rematch
+    }
+
+    /// Starts `command` in a subshell whose stdin and stdout are pipes to the shell.
+    pub fn spawn_coprocess(&mut self, command: &str) -> Coprocess {
+        unsafe {
+            let mut child = subshell_spawn_coprocess(command.as_ptr(), command.len() as u32);
+
+            // It mustn't be reaped before it is known
+            enter_critical_section();
+            let child = subshell_run_command(self as *mut CliParser as *mut std::ffi::c_void, &mut child);
+            self.coprocess_statuses.insert(child.pid, None);
+            exit_critical_section();
+
+            Coprocess::from_fds(child.pid, child.stdout[PIPE_READ], child.stdin[PIPE_WRITE])
+        }
+    }
+
+    /// Waits for the coprocess `pid` to exit, returning its status, or `None`
+    /// if it isn't a coprocess or was already waited for.
+    pub fn wait_coprocess(&mut self, pid: i32) -> Option<i32> {
+        // The status is filled in by the SIGCHLD handler
+        unsafe { enter_critical_section(); }
+        let status = loop {
+            match self.coprocess_statuses.get(&pid) {
+                None => break None,
+                Some(Some(status)) => {
+                    let status = *status;
+                    self.coprocess_statuses.remove(&pid);
+                    break Some(status);
+                },
+                Some(None) => unsafe { wait_for_signal() },
+            }
+        };
+        unsafe { exit_critical_section(); }
+
+        status
+    }
+
+    /// Prepares a forked copy of the shell for running a subshell: the parent's
+    /// jobs aren't ours to wait for and its traps, other than ignored signals,
+    /// don't apply.
+    fn enter_subshell(&mut self) {
+        self.jobs.clear();
+        self.children.clear();
+        self.exit_statuses.clear();
+        self.cur_job = None;
+        self.traps.retain(|_, command| command.is_empty());
+        self.in_subshell = true;
+        self.substitution_fds.clear();
+        self.substitution_statuses.clear();
+        self.coprocesses.clear();
+        self.coprocess_statuses.clear();
+        self.pipeline_pids.clear();
+    }
+
+    /// Waits for the processes of a pipeline whose builtin ran in the shell.
+    /// They may have finished meanwhile, and their job moved.
+    fn wait_for_pipeline(&mut self, pids: &[i32]) {
+        unsafe { enter_critical_section(); }
+        self.pipeline_pids.retain(|pid| !pids.contains(pid));
+        self.cur_job = self.jobs.iter().position(|job| job.iter().any(|pid| pids.contains(pid)));
+        self.should_wait = self.cur_job.is_some();
+        unsafe { exit_critical_section(); }
+
+        self.wait_for_children_to_finish();
+    }
+
+    fn wait_for_children_to_finish(&mut self) {
+        while unsafe { std::ptr::read_volatile(&self.should_wait) } {
+            if !self.get_current_job().is_some_and(|j| !j.is_empty()) {
+                break;
+            }
+        }
+    }
+
+    /// The status of a finished job, which is that of its last command or,
+    /// with pipefail, of its last failing command.
+    fn job_status(&mut self, pids: &[i32]) -> i32 {
+        // The SIGCHLD handler inserts into the statuses, possibly reallocating them
+        unsafe { enter_critical_section(); }
+        let statuses: Vec<i32> = pids.iter()
+            .map(|pid| self.exit_statuses.remove(pid).unwrap_or(0))
+            .collect();
+        unsafe { exit_critical_section(); }
+
+        if self.options.pipefail {
+            statuses.into_iter().rev().find(|s| *s != 0).unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(0)
+        }
+    }
+
+    pub fn get_option(&self, name: &str) -> Option<bool> {
+        self.options.get(name)
+    }
+
+    pub fn set_option(&mut self, name: &str, value: bool) -> bool {
+        self.options.set(name, value)
+    }
+
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    pub fn run_hooks<A>(&self, event: &str, args: A) where A: for<'lua> rlua::ToLuaMulti<'lua> + Clone {
+        self.lua_parser.run_hooks(event, args);
+    }
+
+    pub fn run_command_not_found_hooks(&self, program: &str, command: &str) -> Option<lua_parser::HookReply> {
+        self.lua_parser.run_reply_hooks("command_not_found", (program.to_string(), command.to_string()))
+    }
+
+    fn try_wait(pid: u32) -> Result<Option<i32>, ()> {
+        unsafe {
+            let status = try_wait_process(pid);
+            if status == PROCESS_RUNNING {
+                    Ok(None)
+            } else if status == PROCESS_STOPPED {
+                Ok(Some(PROCESS_STOPPED))
+            } else {
+                Err(())
+            }
+        }
+    }
 
     fn command_is_valid(dir: &str, path: &str) -> bool {
         let path_to_check = std::path::Path::new(&format!("{}/{}", dir, path)).to_owned();
@@ -796,7 +2612,7 @@ impl<'a> CliParser<'a> {
 
     fn check_validity_of_program(command: &Command) -> bool {
 
-        if Self::is_builtin(command) {
+        if Self::is_builtin(command) || split_group(&command[0]).is_some() {
             true
         } else if Self::is_lua_command(&command[0]) {
             true
@@ -804,70 +2620,41 @@ impl<'a> CliParser<'a> {
             true
         } else if Self::command_is_valid("", &command[0]) {
             true
-        } else if let Ok(path) = std::env::var("PATH") {
-            for dir in path.split(":") {
-                if Self::command_is_valid(dir, &command[0]) {
-                    return true;
-                }
-            }
-            false
         } else {
-            false
-        }
-    }
-
-    fn check_for_possible_correction_in_dir(dir: &str, inp: &str) -> Option<(String, String)> {
-
-        let dir_path = std::path::Path::new(dir);
-
-        if let Err(_) = std::fs::read_dir(dir_path) {
-            return None;
-        }
-
-        for f in std::fs::read_dir(dir_path).unwrap().sorted_by(|a, b| a.as_ref().unwrap().file_name().cmp(&b.as_ref().unwrap().file_name())) {
-            if let Ok(entry) = f {
-                let option = entry.file_name();
-
-                if !inp.starts_with(".") && option.to_string_lossy().starts_with(".") {
-                    continue;
-                }
-
-                log!(LogLevel::Debug, "Comparing {} to {}", inp, option.to_string_lossy());
-
-                if strsim::jaro_winkler(inp, &option.to_string_lossy()) > STR_SIM_THRESHOLD {
-                    return Some((option.to_string_lossy().to_string(), dir.to_string()));
-                }
-            }
+            command_hash::hit(&command[0]).is_some()
         }
-
-        None
     }
 
-    fn has_possible_correction_in_same_dir(inp: &str) -> Option<(String, String)> {
+    pub fn correction(name: String, origin: &str, inp: &str) -> Option<Correction> {
+        let score = strsim::jaro_winkler(inp, &name);
 
-        if let Ok(cur_dir) = env::current_dir() {
-            Self::check_for_possible_correction_in_dir(&cur_dir.to_string_lossy(), inp)
+        if score > STR_SIM_THRESHOLD && name != inp {
+            Some(Correction { name, origin: origin.to_string(), score })
         } else {
             None
         }
     }
 
-    fn has_possible_correction_in_path(inp: &str) -> Option<(String, String)> {
-        if let Ok(path) = std::env::var("PATH") {
-            for dir in path.split(":") {
-                if let Some(opt) = Self::check_for_possible_correction_in_dir(dir, inp) {
-                    return Some(opt);
-                }
-            }
-        }
+    pub fn check_for_possible_corrections_in_dir(dir: &str, inp: &str) -> Vec<Correction> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
 
-        None
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|option| inp.starts_with(".") || !option.starts_with("."))
+            .filter_map(|option| {
+                log!(LogLevel::Debug, "Comparing {} to {}", inp, option);
+                Self::correction(option, dir, inp)
+            })
+            .collect()
     }
 
     fn get_pid(child: &ChildProcess) -> u32 {
         match child {
             ChildProcess::Bash(p) => p.id(),
-            ChildProcess::Lua(p)  => p.pid as u32,
+            ChildProcess::Lua(p) | ChildProcess::Subshell(p) => p.pid as u32,
         }
     }
 
@@ -879,15 +2666,35 @@ impl<'a> CliParser<'a> {
         }
     }
 
-    pub fn get_possible_correction(inp: &str) -> (String, String) {
+    /// Returns up to `max` commands similar to `inp`, best match first. Builtins, aliases,
+    /// Lua functions and executables in the current directory and `PATH` are considered.
+    pub fn get_possible_corrections(&self, inp: &str, max: usize) -> Vec<Correction> {
+        let mut corrections = Vec::new();
 
-        if let Some(correction) = Self::has_possible_correction_in_same_dir(inp) {
-            correction
-        } else if let Some(correction) = Self::has_possible_correction_in_path(inp) {
-            correction
-        } else {
-            ("No solution found".to_string(), "file system".to_string())
+        for builtin in Self::get_builtin_commands() {
+            corrections.extend(Self::correction(builtin.to_string(), "builtin", inp));
+        }
+
+        for alias in self.aliases.keys() {
+            corrections.extend(Self::correction(alias.to_string(), "alias", inp));
+        }
+
+        for (function, score) in self.lua_parser.get_possible_corrections(inp, STR_SIM_THRESHOLD) {
+            corrections.push(Correction { name: function, origin: "lua".to_string(), score });
+        }
+
+        if let Ok(cur_dir) = env::current_dir() {
+            corrections.append(&mut Self::check_for_possible_corrections_in_dir(&cur_dir.to_string_lossy(), inp));
+        }
+
+        for (executable, dir) in command_hash::get_executables() {
+            corrections.extend(Self::correction(executable, &dir, inp));
         }
+
+        // Stable sort, so earlier sources win ties
+        corrections.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        corrections.into_iter().unique_by(|c| c.name.clone()).take(max).collect()
     }
 
     pub fn kill(&mut self, sig: i32) {
@@ -916,6 +2723,281 @@ impl<'a> CliParser<'a> {
     }
 }
 
+/// Splits `command` at every unquoted occurrence of one of `separators` outside of
+/// subshells and groups, returning each part along with the separator that ended it.
+fn split_unquoted<'s>(command: &'s str, separators: &[&str]) -> Vec<(&'s str, &'s str)> {
+    let bytes = command.as_bytes();
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if escaped {
+            escaped = false;
+        } else if c == b'\\' && quote != Some(b'\'') {
+            escaped = true;
+        } else if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+        } else if c == b'\'' || c == b'"' {
+            quote = Some(c);
+        } else if group_delimiter(bytes, i) != 0 {
+            depth += group_delimiter(bytes, i);
+        } else if depth > 0 {
+            // Separators within subshells and groups belong to them
+        } else if let Some(sep) = separators.iter().find(|sep| bytes[i..].starts_with(sep.as_bytes())) {
+            parts.push((&command[start..i], &command[i..i + sep.len()]));
+            i += sep.len();
+            start = i;
+            continue;
+        }
+        i += 1;
+    }
+
+    parts.push((&command[start..], ""));
+    parts
+}
+
+/// Whether the byte at `i` opens (1) or closes (-1) a subshell, group, `[[ ... ]]`
+/// or `select` loop. Braces, brackets and keywords only count as separate words,
+/// so that e.g. `a{b,c}` isn't mistaken for a group.
+fn group_delimiter(bytes: &[u8], i: usize) -> i32 {
+    let before = i.checked_sub(1).map(|j| bytes[j]);
+    let after = bytes.get(i + 1).copied();
+
+    match bytes[i] {
+        b'(' => 1,
+        b')' => -1,
+        b'[' if is_keyword_at(bytes, i, b"[[") => 1,
+        b']' if is_closing_brackets(bytes, i) => -1,
+        b's' if is_keyword_at(bytes, i, b"select") => 1,
+        b'd' if is_keyword_at(bytes, i, b"done") => -1,
+        b'{' if before.is_none_or(|b| b" \t\n;&|(".contains(&b)) && after.is_some_and(|a| a.is_ascii_whitespace()) => 1,
+        b'}' if before.is_some_and(|b| b" \t\n;".contains(&b)) && after.is_none_or(|a| b" \t\n;&|)>".contains(&a)) => -1,
+        _ => 0,
+    }
+}
+
+/// Whether `]]` is a separate word at `i`.
+fn is_closing_brackets(bytes: &[u8], i: usize) -> bool {
+    let before = i.checked_sub(1).map(|j| bytes[j]);
+    let after = bytes.get(i + 2).copied();
+
+    bytes.get(i + 1) == Some(&b']')
+        && before.is_none_or(|b| b" \t\n;&|(".contains(&b))
+        && after.is_none_or(|a| b" \t\n;&|)".contains(&a))
+}
+
+/// Whether `keyword` is a separate word at `i`, in command position.
+fn is_keyword_at(bytes: &[u8], i: usize, keyword: &[u8]) -> bool {
+    let before = bytes[..i].iter().rev().find(|b| !b" \t".contains(b));
+    let after = bytes.get(i + keyword.len());
+
+    bytes[i..].starts_with(keyword)
+        && before.is_none_or(|b| b"\n;&|({".contains(b))
+        && after.is_none_or(|a| b" \t\n;&|)".contains(a))
+}
+
+/// Splits the rest of `select name in words; do commands; done` into the part
+/// before `do` and the commands.
+fn split_loop(command: &str) -> Option<(&str, &str)> {
+    let command = command.trim_end().strip_suffix("done")?;
+    let (header, _) = split_unquoted(command, &[";"]).into_iter().next()?;
+    let body = strip_keyword(command.get(header.len() + 1..)?, "do")?;
+
+    Some((header, body))
+}
+
+/// Splits a command starting with a subshell or group into its kind, the
+/// commands within it and whatever follows it, e.g. a redirection.
+fn split_group(command: &str) -> Option<(GroupKind, &str, &str)> {
+    let command = command.trim_start();
+    let bytes = command.as_bytes();
+    let kind = match bytes.first()? {
+        b'(' => GroupKind::Subshell,
+        b'{' if group_delimiter(bytes, 0) == 1 => GroupKind::Group,
+        _ => return None,
+    };
+
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+
+    for (i, &c) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if c == b'\\' && quote != Some(b'\'') {
+            escaped = true;
+        } else if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+        } else if c == b'\'' || c == b'"' {
+            quote = Some(c);
+        } else {
+            depth += group_delimiter(bytes, i);
+            if depth == 0 {
+                return Some((kind, &command[1..i], &command[i + 1..]));
+            }
+        }
+    }
+
+    None
+}
+
+/// The rest of `command` if it starts with the word `keyword`.
+fn strip_keyword<'s>(command: &'s str, keyword: &str) -> Option<&'s str> {
+    command.trim_start()
+        .strip_prefix(keyword)
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// The file descriptors named by the `/dev/fd/N` paths in `arg`.
+fn dev_fds(arg: &str) -> Vec<i32> {
+    arg.match_indices("/dev/fd/")
+        .filter_map(|(i, path)| {
+            let rest = &arg[i + path.len()..];
+            rest[..rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())].parse().ok()
+        })
+        .collect()
+}
+
+/// Finds the first unquoted `<(...)` or `>(...)` word of `command`, returning
+/// its byte range and whether the commands within are an input (`<`).
+fn find_process_substitution(command: &str) -> Option<(usize, usize, bool)> {
+    let bytes = command.as_bytes();
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, &c) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if c == b'\\' && quote != Some(b'\'') {
+            escaped = true;
+        } else if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+        } else if c == b'\'' || c == b'"' {
+            quote = Some(c);
+        } else if (c == b'<' || c == b'>')
+            && bytes.get(i + 1) == Some(&b'(')
+            && (i == 0 || bytes[i - 1].is_ascii_whitespace())
+        {
+            let (_, body, _) = split_group(&command[i + 1..])?;
+            return Some((i, i + body.len() + 3, c == b'<'));
+        }
+    }
+
+    None
+}
+
+/// Expands aliases in every command position of `command`. Alias bodies are expanded
+/// recursively, except for aliases already being expanded, which stops loops such as
+/// `alias ls='ls --color'`. Names that aren't plain aliases are passed to `lua_alias`
+/// along with their arguments, which may return a command to replace them with.
+fn expand_aliases(
+    aliases: &HashMap<String, String>,
+    command: &str,
+    expanding: &mut Vec<String>,
+    lua_alias: &dyn Fn(&str, &[String]) -> Option<String>
+) -> String {
+    let mut expanded = String::new();
+
+    for (segment, separator) in split_unquoted(command, &["&&", "||", "|", ";"]) {
+        let trimmed = segment.trim_start();
+        let leading = &segment[..segment.len() - trimmed.len()];
+        let (name, rest) = trimmed.split_at(trimmed.find(char::is_whitespace).unwrap_or(trimmed.len()));
+
+        if name.is_empty() || expanding.iter().any(|e| e == name) {
+            expanded.push_str(segment);
+        } else if let Some(body) = aliases.get(name) {
+            expanding.push(name.to_string());
+            let body = expand_aliases(aliases, body, expanding, lua_alias);
+            expanding.pop();
+
+            expanded.push_str(leading);
+            expanded.push_str(&body);
+            expanded.push_str(rest);
+        } else if let Some(replacement) = lua_alias(name, &CliParser::parse_command(rest).unwrap_or_default()) {
+            expanding.push(name.to_string());
+            let replacement = expand_aliases(aliases, &replacement, expanding, lua_alias);
+            expanding.pop();
+
+            expanded.push_str(leading);
+            expanded.push_str(&replacement);
+        } else {
+            expanded.push_str(segment);
+        }
+
+        expanded.push_str(separator);
+    }
+
+    expanded
+}
+
+fn quote_alias(body: &str) -> String {
+    format!("'{}'", body.replace('\'', "'\\''"))
+}
+
+/// Quotes `word` for display if it wouldn't be read back as a single word.
+pub fn quote_word(word: &str) -> String {
+    let is_plain = |c: char| c.is_alphanumeric() || "-_./=:,+@%^~".contains(c);
+    if !word.is_empty() && word.chars().all(is_plain) {
+        word.to_string()
+    } else {
+        quote_alias(word)
+    }
+}
+
+/// Writes the output of a builtin, returning its exit status. A closed pipe
+/// isn't worth a message.
+fn write_output(stdout: &mut dyn Write, output: &str) -> i32 {
+    match stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush()) {
+        Ok(()) => 0,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                eprintln!("luabster: write error: {}", e);
+            }
+            1
+        }
+    }
+}
+
+/// Splits a line read by `read` into at most `count` fields separated by the
+/// characters of `ifs`, the last field getting the rest of the line. Runs of
+/// whitespace in `ifs` count as a single separator.
+fn split_fields(line: &str, ifs: &str, count: usize) -> Vec<String> {
+    let is_space = |c: char| c.is_whitespace() && ifs.contains(c);
+    let mut fields = Vec::new();
+    let mut rest = line.trim_matches(is_space);
+
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let Some(i) = rest.find(|c| ifs.contains(c)) else {
+            break;
+        };
+        fields.push(rest[..i].to_string());
+
+        let separator = rest[i..].chars().next().unwrap();
+        rest = rest[i + separator.len_utf8()..].trim_start_matches(is_space);
+        if separator.is_whitespace() {
+            if let Some(after) = rest.strip_prefix(|c: char| !c.is_whitespace() && ifs.contains(c)) {
+                rest = after.trim_start_matches(is_space);
+            }
+        }
+    }
+
+    if !rest.is_empty() {
+        fields.push(rest.to_string());
+    }
+    fields
+}
+
 impl From<std::process::Child> for Child {
     fn from(mut value: std::process::Child) -> Self {
         let empty_string = std::ffi::CString::new("").unwrap();
@@ -940,7 +3022,7 @@ impl ChildProcess {
     fn get_pid(&self) -> i32 {
         match self {
             Self::Bash(c) => c.id() as i32,
-            Self::Lua(c)  => c.pid
+            Self::Lua(c) | Self::Subshell(c) => c.pid
         }
     }
 }
@@ -957,7 +3039,12 @@ struct OutFile {
 }
 
 impl OutFile {
-    pub fn new(file_name: &str) -> std::io::Result<Box<Self>>  {
+    /// Creates or truncates `file_name`. With `noclobber`, existing regular
+    /// files are left alone and an error is returned instead.
+    pub fn new(file_name: &str, noclobber: bool) -> std::io::Result<Box<Self>>  {
+        if noclobber && std::fs::metadata(file_name).is_ok_and(|m| m.is_file()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "cannot overwrite existing file"));
+        }
         let file = std::fs::File::create(file_name)?;
         Ok(Box::new(Self { file }))
     }
@@ -1001,16 +3088,61 @@ pub extern "C" fn parser_stop(parser: *mut std::ffi::c_void, sig: i32) {
     }
 }
 
+/// Runs the commands of a subshell in the forked child, returning its exit status.
+///
+/// # Safety
+/// `parser` must point to the shell's `CliParser` and `cmd` to `cmdlen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn run_subshell(parser: *mut std::ffi::c_void, cmd: *const std::ffi::c_uchar, cmdlen: i32) -> i32 {
+    let p: &mut CliParser = &mut *(parser as *mut CliParser);
+    let cmd = String::from_utf8_lossy(std::slice::from_raw_parts(cmd, cmdlen as usize)).to_string();
+
+    // The fork happened within the parent's critical section
+    exit_critical_section();
+    let builtin = p.forked_builtin.take();
+    let jobs = p.jobs.clone();
+    p.enter_subshell();
+
+    if let Some(builtin) = builtin {
+        // So that e.g. `jobs | wc -l` still sees the shell's jobs
+        p.jobs = jobs;
+        let mut streams = Streams::standard();
+        p.last_status = p.run_builtin(&builtin, &mut streams);
+        _ = streams.stdout.flush();
+    } else if let Err(Errors::NoProgramFound(program)) = p.parse_inputs(&cmd) {
+        eprintln!("luabster: {}: command not found", program);
+    }
+    _ = std::io::Write::flush(&mut std::io::stdout());
+
+    p.last_status
+}
+
 #[no_mangle]
-pub extern "C" fn parser_child_reaped(parser: *mut std::ffi::c_void, pid: i32) {
+pub extern "C" fn parser_child_reaped(parser: *mut std::ffi::c_void, pid: i32, status: i32) {
     unsafe {
         let p: &mut CliParser = &mut *(parser as *mut CliParser);
+
+        if let Some(coprocess_status) = p.coprocess_statuses.get_mut(&pid) {
+            *coprocess_status = Some(status);
+            return;
+        }
+        if let Some(substitution_status) = p.substitution_statuses.get_mut(&pid) {
+            *substitution_status = Some(status);
+            return;
+        }
         
-        let job_idx = p.jobs.iter().enumerate().filter(|(_, j)| j.contains(&pid)).nth(0).unwrap().0;
+        // Not one of ours, e.g. a command substitution
+        let Some((job_idx, _)) = p.jobs.iter().find_position(|j| j.contains(&pid)) else {
+            return;
+        };
         let pid_idx = p.jobs[job_idx].iter().find_position(|p| **p == pid).unwrap().0;
         p.jobs[job_idx].swap_remove(pid_idx);
         p.children.remove(&pid);
 
+        if p.cur_job.is_some_and(|idx| idx == job_idx) || p.pipeline_pids.contains(&pid) {
+            p.exit_statuses.insert(pid, status);
+        }
+
         if p.jobs[job_idx].is_empty() {
             p.jobs.swap_remove(job_idx);
 
@@ -1018,7 +3150,124 @@ pub extern "C" fn parser_child_reaped(parser: *mut std::ffi::c_void, pid: i32) {
                 p.cur_job = None;
 
                 std::ptr::write_volatile(&mut p.should_wait, false);
+            } else if p.cur_job.is_some_and(|idx| idx == p.jobs.len()) {
+                // The current job was moved into the removed slot
+                p.cur_job = Some(job_idx);
             }
         }
     }
 }
+
+
+
+#[test]
+fn test_split_unquoted() {
+    assert_eq!(split_unquoted("ls | wc", &["|"]), [("ls ", "|"), (" wc", "")]);
+    assert_eq!(split_unquoted("echo 'a | b' | wc", &["|"]), [("echo 'a | b' ", "|"), (" wc", "")]);
+    assert_eq!(split_unquoted("echo \"a && b\" && ls", &["&&"]), [("echo \"a && b\" ", "&&"), (" ls", "")]);
+    assert_eq!(split_unquoted("echo a\\;b;ls", &[";"]), [("echo a\\;b", ";"), ("ls", "")]);
+    assert_eq!(split_unquoted("( cd /tmp; ls ) | wc", &[";"]), [("( cd /tmp; ls ) | wc", "")]);
+    assert_eq!(split_unquoted("{ a; b; }; c", &[";"]), [("{ a; b; }", ";"), (" c", "")]);
+    assert_eq!(split_unquoted("echo a{b,c}; d", &[";"]), [("echo a{b,c}", ";"), (" d", "")]);
+    assert_eq!(split_unquoted("[[ a && b ]] && c", &["&&"]), [("[[ a && b ]] ", "&&"), (" c", "")]);
+    assert_eq!(split_unquoted("echo a[[b]]; c", &[";"]), [("echo a[[b]]", ";"), (" c", "")]);
+    assert_eq!(split_unquoted("echo [[; echo second", &[";"]), [("echo [[", ";"), (" echo second", "")]);
+    assert_eq!(split_unquoted("select x in a; do b; done; c", &[";"]), [("select x in a; do b; done", ";"), (" c", "")]);
+    assert_eq!(split_unquoted("echo select; echo done", &[";"]), [("echo select", ";"), (" echo done", "")]);
+}
+
+#[test]
+fn test_split_group() {
+    assert_eq!(split_group("( a; (b) ) > f"), Some((GroupKind::Subshell, " a; (b) ", " > f")));
+    assert_eq!(split_group("{ a; echo '}'; }"), Some((GroupKind::Group, " a; echo '}'; ", "")));
+    assert_eq!(split_group("{a}"), None);
+    assert_eq!(split_group("( a"), None);
+}
+
+#[test]
+fn test_find_process_substitution() {
+    assert_eq!(find_process_substitution("diff <(sort a) <(sort b)"), Some((5, 14, true)));
+    assert_eq!(find_process_substitution("tee >(wc -l) > f"), Some((4, 12, false)));
+    assert_eq!(find_process_substitution("echo '<(a)' a>(b)"), None);
+    assert_eq!(dev_fds("--old=/dev/fd/63,/dev/fd/7x"), vec![63, 7]);
+}
+
+#[test]
+fn test_split_fields() {
+    assert_eq!(split_fields("  a  b  c ", DEFAULT_IFS, 2), ["a", "b  c"]);
+    assert_eq!(split_fields("a b", DEFAULT_IFS, 3), ["a", "b"]);
+    assert_eq!(split_fields("a:b::c", ":", 4), ["a", "b", "", "c"]);
+    assert_eq!(split_fields("a : b", " :", 2), ["a", "b"]);
+    assert_eq!(split_fields("   ", DEFAULT_IFS, 1), Vec::<String>::new());
+}
+
+#[test]
+fn test_expand_aliases() {
+    let aliases: HashMap<String, String> = [
+        ("ll", "ls -l"),
+        ("ls", "ls --color"),
+        ("gs", "git status | less"),
+        ("up", "cd .. && ll"),
+        ("a", "b"),
+        ("b", "a"),
+    ].into_iter().map(|(n, b)| (n.to_string(), b.to_string())).collect();
+
+    let no_lua = |_: &str, _: &[String]| None;
+
+    assert_eq!(expand_aliases(&aliases, "ll /tmp", &mut Vec::new(), &no_lua), "ls --color -l /tmp");
+    assert_eq!(expand_aliases(&aliases, "gs -S", &mut Vec::new(), &no_lua), "git status | less -S");
+    assert_eq!(expand_aliases(&aliases, "up; ls | ll", &mut Vec::new(), &no_lua), "cd .. && ls --color -l; ls --color | ls --color -l");
+    assert_eq!(expand_aliases(&aliases, "echo ll", &mut Vec::new(), &no_lua), "echo ll");
+    assert_eq!(expand_aliases(&aliases, "a", &mut Vec::new(), &no_lua), "a");
+
+    let lua = |name: &str, args: &[String]| match name {
+        "mkcd" => Some(format!("mkdir {0} && cd {0}", args[0])),
+        "loop" => Some("loop".to_string()),
+        _ => None,
+    };
+
+    assert_eq!(expand_aliases(&aliases, "mkcd 'new dir'", &mut Vec::new(), &lua), "mkdir new dir && cd new dir");
+    assert_eq!(expand_aliases(&aliases, "loop", &mut Vec::new(), &lua), "loop");
+}
+
+#[test]
+fn test_builtin_streams() {
+    let home = tempfile::tempdir().unwrap();
+    let mut parser = CliParser::new(home.path().to_str().unwrap());
+    let command = |s: &str| s.split(' ').map(String::from).collect::<Command>();
+    let streams = |stdout: Box<dyn Write>, stderr: Box<dyn Write>| Streams { stdin: Box::new(io::empty()), stdout, stderr };
+    let read_all = |mut reader: io::PipeReader| {
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+        s
+    };
+
+    // The exit status, with the error on the builtin's stderr
+    let (stderr, writer) = io::pipe().unwrap();
+    let mut failing = streams(Box::new(io::sink()), Box::new(writer));
+    assert_eq!(parser.run_builtin(&command("shift x"), &mut failing), 2);
+    assert_eq!(parser.run_builtin(&command("alias ll=ls"), &mut failing), 0);
+    drop(failing);
+    assert_eq!(read_all(stderr), "luabster: shift: x: numeric argument required\n");
+
+    // `alias | cat`, writing to a pipe
+    let (stdout, writer) = io::pipe().unwrap();
+    assert_eq!(parser.run_builtin(&command("alias ll"), &mut streams(Box::new(writer), Box::new(io::sink()))), 0);
+    assert_eq!(read_all(stdout), "alias ll='ls'\n");
+
+    // `jobs > file`, writing to a file
+    let path = home.path().join("jobs");
+    parser.jobs = vec![vec![12, 13]];
+    let file = File::create(&path).unwrap();
+    assert_eq!(parser.run_builtin(&command("jobs"), &mut streams(Box::new(file), Box::new(io::sink()))), 0);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "[1]  12 13\n");
+    parser.jobs.clear();
+
+    // `echo x | read v`, with `read` as the last command run in the shell
+    let (reader, mut writer) = io::pipe().unwrap();
+    writer.write_all(b"x\n").unwrap();
+    drop(writer);
+    assert_eq!(parser.run_builtin_in_process(&command("read v"), Some(reader.into_raw_fd()), &mut None), 0);
+    assert_eq!(parser.variable("v").as_deref(), Some("x"));
+    assert!(env::var_os("v").is_none());
+}
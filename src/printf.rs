@@ -0,0 +1,259 @@
+use std::ffi::{c_char, CString};
+
+
+extern "C" {
+    fn snprintf(buf: *mut c_char, len: usize, format: *const c_char, ...) -> i32;
+}
+
+
+/// Interprets the backslash escapes of `echo -e` and `%b`. Also returns
+/// whether a `\c` asked for the output to end there.
+pub fn unescape(s: &str) -> (String, bool) {
+    let mut out = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('a') => out.push('\x07'),
+            Some('b') => out.push('\x08'),
+            Some('e') | Some('E') => out.push('\x1b'),
+            Some('f') => out.push('\x0c'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\x0b'),
+            Some('\\') => out.push('\\'),
+            Some('c') => return (out, true),
+            Some('0') => out.push(char_from_digits(&mut chars, 8, 3)),
+            Some('x') => out.push(char_from_digits(&mut chars, 16, 2)),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            },
+            None => out.push('\\'),
+        }
+    }
+
+    (out, false)
+}
+
+fn char_from_digits(chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max_digits: usize) -> char {
+    let mut value = 0;
+    for _ in 0..max_digits {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(d) => {
+                value = value * radix + d;
+                chars.next();
+            },
+            None => break,
+        }
+    }
+    char::from_u32(value).unwrap_or('\0')
+}
+
+/// Formats `args` like printf(1), reusing `format` until all arguments are
+/// consumed. Arguments that aren't valid numbers are reported in the errors
+/// and formatted as 0.
+pub fn format(format: &str, args: &[String]) -> (String, Vec<String>) {
+    let (format, _) = unescape(format);
+    let mut out = String::new();
+    let mut errors = Vec::new();
+    let mut args = args.iter().map(String::as_str);
+    let mut consumed = false;
+
+    loop {
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            if chars.next_if_eq(&'%').is_some() {
+                out.push('%');
+                continue;
+            }
+
+            let mut spec = String::from("%");
+            while let Some(flag) = chars.next_if(|c| "-+ #0".contains(*c)) {
+                spec.push(flag);
+            }
+            let mut read_number = |chars: &mut std::iter::Peekable<std::str::Chars>, spec: &mut String| {
+                if chars.next_if_eq(&'*').is_some() {
+                    consumed = true;
+                    let n = parse_int(args.next().unwrap_or(""), &mut errors);
+                    spec.push_str(&n.to_string());
+                } else {
+                    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                        spec.push(d);
+                    }
+                }
+            };
+            read_number(&mut chars, &mut spec);
+            if chars.next_if_eq(&'.').is_some() {
+                spec.push('.');
+                read_number(&mut chars, &mut spec);
+            }
+
+            let Some(conversion) = chars.next() else {
+                out.push_str(&spec);
+                break;
+            };
+            let arg = args.next();
+            consumed |= arg.is_some();
+            let arg = arg.unwrap_or("");
+
+            match conversion {
+                'd' | 'i' => out.push_str(&c_format(&format!("{}lld", spec), parse_int(arg, &mut errors))),
+                'o' | 'u' | 'x' | 'X' => {
+                    out.push_str(&c_format(&format!("{}ll{}", spec, conversion), parse_int(arg, &mut errors) as u64));
+                },
+                'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+                    out.push_str(&c_format(&format!("{}{}", spec, conversion), parse_float(arg, &mut errors)));
+                },
+                'c' => out.push_str(&pad(&spec, &arg.chars().take(1).collect::<String>())),
+                's' => out.push_str(&pad(&spec, arg)),
+                'b' => {
+                    let (arg, stop) = unescape(arg);
+                    out.push_str(&pad(&spec, &arg));
+                    if stop {
+                        return (out, errors);
+                    }
+                },
+                c => {
+                    errors.push(format!("%{}: invalid directive", c));
+                    out.push_str(&spec);
+                    out.push(c);
+                },
+            }
+        }
+
+        // Like printf(1), the format is reused for the remaining arguments
+        if !consumed || args.len() == 0 {
+            break;
+        }
+        consumed = false;
+    }
+
+    (out, errors)
+}
+
+/// Formats a number with the C conversion `spec`.
+fn c_format<T: CNumber>(spec: &str, value: T) -> String {
+    let spec = CString::new(spec).unwrap_or_default();
+    let mut buf = vec![0u8; 512];
+    let len = unsafe { value.snprintf(buf.as_mut_ptr() as *mut c_char, buf.len(), spec.as_ptr()) };
+    buf.truncate(len.clamp(0, buf.len() as i32 - 1) as usize);
+    String::from_utf8_lossy(&buf).to_string()
+}
+
+trait CNumber {
+    unsafe fn snprintf(self, buf: *mut c_char, len: usize, spec: *const c_char) -> i32;
+}
+
+impl CNumber for i64 {
+    unsafe fn snprintf(self, buf: *mut c_char, len: usize, spec: *const c_char) -> i32 {
+        snprintf(buf, len, spec, self)
+    }
+}
+
+impl CNumber for u64 {
+    unsafe fn snprintf(self, buf: *mut c_char, len: usize, spec: *const c_char) -> i32 {
+        snprintf(buf, len, spec, self)
+    }
+}
+
+impl CNumber for f64 {
+    unsafe fn snprintf(self, buf: *mut c_char, len: usize, spec: *const c_char) -> i32 {
+        snprintf(buf, len, spec, self)
+    }
+}
+
+/// Applies the width, precision and `-` flag of `spec` to a string.
+fn pad(spec: &str, s: &str) -> String {
+    let spec = &spec[1..];
+    let left = spec.contains('-');
+    let spec = spec.trim_start_matches(|c| "-+ #0".contains(c));
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, precision.parse().ok().or(Some(0))),
+        None => (spec, None),
+    };
+    let width: usize = width.parse().unwrap_or(0);
+
+    let s: String = match precision {
+        Some(precision) => s.chars().take(precision).collect(),
+        None => s.to_string(),
+    };
+
+    if left {
+        format!("{:<width$}", s, width = width)
+    } else {
+        format!("{:>width$}", s, width = width)
+    }
+}
+
+/// Parses a printf(1) integer: decimal, `0x` hex, `0` octal or `'c` for the
+/// value of the character `c`.
+fn parse_int(arg: &str, errors: &mut Vec<String>) -> i64 {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() {
+        return 0;
+    }
+    if let Some(c) = trimmed.strip_prefix('\'').or(trimmed.strip_prefix('"')) {
+        return c.chars().next().map_or(0, |c| c as i64);
+    }
+
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+
+    match value {
+        Ok(n) => if negative { -n } else { n },
+        Err(_) => {
+            errors.push(format!("{}: invalid number", arg));
+            0
+        }
+    }
+}
+
+fn parse_float(arg: &str, errors: &mut Vec<String>) -> f64 {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() {
+        return 0.0;
+    }
+    if trimmed.starts_with('\'') || trimmed.starts_with('"') || trimmed.contains("0x") {
+        return parse_int(arg, errors) as f64;
+    }
+
+    trimmed.parse().unwrap_or_else(|_| {
+        errors.push(format!("{}: invalid number", arg));
+        0.0
+    })
+}
+
+
+#[test]
+fn test_printf() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+
+    assert_eq!(format("%s-%5s|%-4s|%.2s\\n", &args(&["a", "b", "c", "xyz"])).0, "a-    b|c   |xy\n");
+    assert_eq!(format("%d %05.1f %x %#o %c %%", &args(&["-42", "3.14159", "255", "8", "zebra"])).0, "-42 003.1 ff 010 z %");
+    assert_eq!(format("%s=%d ", &args(&["a", "1", "b", "2", "c"])).0, "a=1 b=2 c=0 ");
+    assert_eq!(format("%*d|%d", &args(&["4", "7", "'A"])).0, "   7|65");
+    assert_eq!(format("%b|%s", &args(&["a\\tb", "a\\tb"])).0, "a\tb|a\\tb");
+    assert_eq!(format("%d", &args(&["x1"])), ("0".to_string(), vec!["x1: invalid number".to_string()]));
+    assert_eq!(format("%.3e", &args(&["1234.5"])).0, "1.234e+03");
+    assert_eq!(unescape("a\\x41\\0102\\cb"), ("aAB".to_string(), true));
+}
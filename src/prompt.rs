@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 use crate::{
     config,
+    line_editor::MODE_PLACEHOLDER,
     log::*,
+    termio,
+    timing,
     tag,
 };
 
@@ -14,6 +17,10 @@ pub struct Prompt<'a> {
     colors: HashMap<&'a str, ColorHex>,
     custom_prompt: Option<String>,
     show_git: bool,
+    show_duration: bool,
+    /// Shortest duration of the last command, in seconds, that is shown.
+    duration_threshold: u32,
+    last_duration: f64,
 }
 
 macro_rules! prompt_prefix {
@@ -32,23 +39,33 @@ const USER_CONFIG_NAME: &str = concat!(prompt_prefix!(), colors_prefix!(), "user
 const HOST_CONFIG_NAME: &str = concat!(prompt_prefix!(), colors_prefix!(), "host");
 const GIT_COLOR_CONFIG_NAME:  &str = concat!(prompt_prefix!(), colors_prefix!(), "git");
 const GIT_ENABLE_CONFIG_NAME: &str = concat!(prompt_prefix!(), "show_git");
+const DURATION_COLOR_CONFIG_NAME: &str = concat!(prompt_prefix!(), colors_prefix!(), "duration");
+const DURATION_ENABLE_CONFIG_NAME: &str = concat!(prompt_prefix!(), "show_duration");
+const DURATION_THRESHOLD_CONFIG_NAME: &str = concat!(prompt_prefix!(), "duration_threshold");
 
 const DIR_DEFAULT_COLOR:  (u8, u8, u8) = (0x00, 0xFF, 0xFF);
 const NAME_DEFAULT_COLOR: (u8, u8, u8) = (0x00, 0x00, 0xFF);
 const HOST_DEFAULT_COLOR: (u8, u8, u8) = (0x00, 0x80, 0x00);
 const GIT_DEFAULT_COLOR:  (u8, u8, u8) = (0xFF, 0x00, 0x00);
 const GIT_DEFAULT_ENABLE: bool = true;
+const DURATION_DEFAULT_COLOR: (u8, u8, u8) = (0xFF, 0xFF, 0x00);
+const DURATION_DEFAULT_ENABLE: bool = false;
+const DURATION_DEFAULT_THRESHOLD: u32 = 2;
 const PROMPT: &str = "LUABSTER ";
 
 
 const fn prompt_configs<'a>() -> &'a [config::ConfigParam<'a>] {
     & tag!{ "prompt",
         "show_git"      =>  GIT_DEFAULT_ENABLE,
+        "show_duration" =>  DURATION_DEFAULT_ENABLE,
+        "duration_threshold" => DURATION_DEFAULT_THRESHOLD,
+        "custom_prompt" => "",
         { "colors",
             "dir"       =>  DIR_DEFAULT_COLOR,
             "user"      =>  NAME_DEFAULT_COLOR,
             "host"      =>  HOST_DEFAULT_COLOR,
             "git"       =>  GIT_DEFAULT_COLOR,
+            "duration"  =>  DURATION_DEFAULT_COLOR,
         },
     }
 }
@@ -65,7 +82,7 @@ impl<'a> config::Configurable<'a> for Prompt<'a> {
     fn with_config(&mut self, configs: &config::Configs) {
         if let Some(p) = configs.get("prompt.custom_prompt") {
             match p {
-                config::ConfigType::String(s) => self.custom_prompt = Some(s.to_string()),
+                config::ConfigType::String(s) if !s.is_empty() => self.custom_prompt = Some(s.to_string()),
                 _ => (),
             }
         }
@@ -75,6 +92,12 @@ impl<'a> config::Configurable<'a> for Prompt<'a> {
                 _ => (),
             }
         }
+        if let Some(config::ConfigType::Toggle(b)) = configs.get(DURATION_ENABLE_CONFIG_NAME) {
+            self.show_duration = *b;
+        }
+        if let Some(config::ConfigType::Number(n)) = configs.get(DURATION_THRESHOLD_CONFIG_NAME) {
+            self.duration_threshold = *n;
+        }
         for (p, default) in prompt_configs() {
             match default.convert() {
                 config::ConfigType::Color(c) => _ = self.colors.insert(p, Self::get_config(p, c, configs)),
@@ -99,6 +122,22 @@ impl<'a> Prompt<'a> {
             colors: HashMap::new(),
             custom_prompt: None,
             show_git: false,
+            show_duration: DURATION_DEFAULT_ENABLE,
+            duration_threshold: DURATION_DEFAULT_THRESHOLD,
+            last_duration: 0.0,
+        }
+    }
+
+    /// Sets how long the last command took, for the duration segment.
+    pub fn set_last_duration(&mut self, secs: f64) {
+        self.last_duration = secs;
+    }
+
+    fn get_duration(&self) -> Option<String> {
+        if self.show_duration && self.last_duration >= self.duration_threshold as f64 {
+            Some(format!(" took {}", timing::format_duration(self.last_duration)))
+        } else {
+            None
         }
     }
 
@@ -118,12 +157,13 @@ impl<'a> Prompt<'a> {
 
     pub fn get(&self, home_dir: &str) -> String {
         if let Some(custom_prompt) = &self.custom_prompt {
-            return custom_prompt.to_string();
+            return Self::show_mode(custom_prompt);
         }
         let dir_color = self.colors.get(DIR_CONFIG_NAME).unwrap();
         let git_color = self.colors.get(GIT_COLOR_CONFIG_NAME).unwrap();
         let user_color = self.colors.get(USER_CONFIG_NAME).unwrap();
         let host_color = self.colors.get(HOST_CONFIG_NAME).unwrap();
+        let duration_color = self.colors.get(DURATION_COLOR_CONFIG_NAME).unwrap();
 
         const USERNAME_KEY: &str = "USER";
         if let Ok(cur_dir) = std::env::current_dir() {
@@ -132,7 +172,8 @@ impl<'a> Prompt<'a> {
                 let cur_branch = Self::get_git_branch(cur_dir).unwrap_or("".to_string()).custom_color(color!(git_color));
                 let user = std::env::var(USERNAME_KEY).unwrap().custom_color(color!(user_color));
                 let hn = hn.to_string_lossy().to_string().custom_color(color!(host_color));
-                format!("[{}@{}] {}{} \n>> ", user, hn, current_dir, if self.show_git { &cur_branch } else { "" })
+                let duration = self.get_duration().unwrap_or_default().custom_color(color!(duration_color));
+                format!("[{}@{}] {}{}{} \n>> ", user, hn, current_dir, if self.show_git { &cur_branch } else { "" }, duration)
             } else {
                 format!("[{}] {} >> ", PROMPT, cur_dir.display().to_string().custom_color(color!(dir_color)))
             }  
@@ -141,6 +182,18 @@ impl<'a> Prompt<'a> {
         }
     }
 
+    /// Leaves a last line showing the edit mode, e.g. `{mode}$ `, to the line
+    /// editor, which redraws it whenever the vi mode changes.
+    fn show_mode(prompt: &str) -> String {
+        let (head, last) = prompt.split_at(prompt.rfind('\n').map_or(0, |i| i + 1));
+        if !last.contains(MODE_PLACEHOLDER) {
+            return prompt.to_string();
+        }
+
+        termio::editor().mode_prompt = Some(last.to_string());
+        head.to_string()
+    }
+
     fn get_config(conf: &str, default: ColorHex, configs: &config::Configs) -> ColorHex {
         if let Some(conf) = configs.get(conf) {
             match conf {
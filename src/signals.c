@@ -4,50 +4,168 @@
 #include <string.h>
 #include <errno.h>
 #include <sys/wait.h>
+#include <sys/resource.h>
 
 
 extern void parser_kill(void *, int);
 extern void parser_stop(void*, int);
-extern void parser_child_reaped(void*, int);
+extern void parser_child_reaped(void*, int, int);
 
 static void sig_handler(int sig);
 static void sigstop_handler(int sig);
 static void sigchld_handler(int sig);
+static void trap_handler(int sig);
 
 static volatile void *parser;
 
+/* Signals with a `trap` set, and those that arrived since the main loop last
+ * ran their handlers. */
+static volatile sig_atomic_t trapped[NSIG];
+static volatile sig_atomic_t pending[NSIG];
+
+/* CPU time used by all children reaped so far, and the largest max RSS among
+ * those reaped since `reset_child_max_rss`, for `time`. */
+static volatile long reaped_user_usec;
+static volatile long reaped_sys_usec;
+static volatile long reaped_max_rss;
+
+static const struct {
+    const char *name;
+    int sig;
+} SIGNAL_NAMES[] = {
+    { "HUP", SIGHUP }, { "INT", SIGINT }, { "QUIT", SIGQUIT }, { "ILL", SIGILL },
+    { "TRAP", SIGTRAP }, { "ABRT", SIGABRT }, { "BUS", SIGBUS }, { "FPE", SIGFPE },
+    { "KILL", SIGKILL }, { "USR1", SIGUSR1 }, { "SEGV", SIGSEGV }, { "USR2", SIGUSR2 },
+    { "PIPE", SIGPIPE }, { "ALRM", SIGALRM }, { "TERM", SIGTERM }, { "CHLD", SIGCHLD },
+    { "CONT", SIGCONT }, { "STOP", SIGSTOP }, { "TSTP", SIGTSTP }, { "TTIN", SIGTTIN },
+    { "TTOU", SIGTTOU }, { "URG", SIGURG }, { "XCPU", SIGXCPU }, { "XFSZ", SIGXFSZ },
+    { "VTALRM", SIGVTALRM }, { "PROF", SIGPROF }, { "WINCH", SIGWINCH }, { "IO", SIGIO },
+    { "SYS", SIGSYS },
+};
+
 const int sig_CONT = SIGCONT;
 const int sig_STOP = SIGSTOP;
 const int PROCESS_EXITED = -1;
 const int PROCESS_STOPPED = SIGSTOP;
 const int PROCESS_RUNNING = 0;
 
-void signal_setup(void *p)
+/* The handler the shell itself needs for `sig`, if any. */
+static void (*shell_handler(int sig))(int)
+{
+    switch (sig) {
+    case SIGINT:
+    case SIGQUIT:
+        return sig_handler;
+    case SIGTSTP:
+        return sigstop_handler;
+    case SIGCHLD:
+        return sigchld_handler;
+    default:
+        return NULL;
+    }
+}
+
+static int set_handler(int sig, void (*handler)(int))
 {
-    parser = p;
     struct sigaction act = {
-        .sa_handler = sig_handler,
+        .sa_handler = handler,
         .sa_flags = 0,
     };
     sigemptyset(&act.sa_mask);
 
-    if (sigaction(SIGINT, &act, NULL)) {
+    return sigaction(sig, &act, NULL);
+}
+
+void signal_setup(void *p)
+{
+    parser = p;
+
+    if (set_handler(SIGINT, sig_handler)) {
         printf("Failed to bind SIGINT: %s\n", strerror(errno));
     }
-    if (sigaction(SIGQUIT, &act, NULL)) {
+    if (set_handler(SIGQUIT, sig_handler)) {
         printf("Failed to bind SIGQUIT: %s\n", strerror(errno));
     }
-    act.sa_handler = sigstop_handler;
-    if (sigaction(SIGTSTP, &act, NULL)) {
+    if (set_handler(SIGTSTP, sigstop_handler)) {
         printf("Failed to bind SIGTSTP: %s\n", strerror(errno));
     }
-
-    act.sa_handler = sigchld_handler;
-    if (sigaction(SIGCHLD, &act, NULL)) {
+    if (set_handler(SIGCHLD, sigchld_handler)) {
         printf("Failed to bind SIGCHLD: %s\n", strerror(errno));
     }
 }
 
+int signal_from_name(const char *name)
+{
+    for (size_t i = 0; i < sizeof(SIGNAL_NAMES) / sizeof(SIGNAL_NAMES[0]); i++) {
+        if (strcmp(SIGNAL_NAMES[i].name, name) == 0)
+            return SIGNAL_NAMES[i].sig;
+    }
+    return -1;
+}
+
+const char *signal_to_name(int sig)
+{
+    for (size_t i = 0; i < sizeof(SIGNAL_NAMES) / sizeof(SIGNAL_NAMES[0]); i++) {
+        if (SIGNAL_NAMES[i].sig == sig)
+            return SIGNAL_NAMES[i].name;
+    }
+    return NULL;
+}
+
+/* Starts recording arrivals of `sig` for its trap. The shell's own handlers
+ * stay in place and record the signal as well. */
+int trap_signal(int sig)
+{
+    if (sig <= 0 || sig >= NSIG)
+        return -1;
+
+    trapped[sig] = 1;
+    return set_handler(sig, shell_handler(sig) ? shell_handler(sig) : trap_handler);
+}
+
+int ignore_signal(int sig)
+{
+    if (sig <= 0 || sig >= NSIG)
+        return -1;
+
+    trapped[sig] = 0;
+    return set_handler(sig, SIG_IGN);
+}
+
+int reset_signal(int sig)
+{
+    if (sig <= 0 || sig >= NSIG)
+        return -1;
+
+    trapped[sig] = 0;
+    pending[sig] = 0;
+    return set_handler(sig, shell_handler(sig) ? shell_handler(sig) : SIG_DFL);
+}
+
+/* Returns a signal whose trap is due and clears it, or 0 if there is none. */
+int take_pending_signal()
+{
+    for (int sig = 1; sig < NSIG; sig++) {
+        if (pending[sig]) {
+            pending[sig] = 0;
+            return sig;
+        }
+    }
+    return 0;
+}
+
+void child_usage(long *user_usec, long *sys_usec, long *max_rss_kb)
+{
+    *user_usec = reaped_user_usec;
+    *sys_usec = reaped_sys_usec;
+    *max_rss_kb = reaped_max_rss;
+}
+
+void reset_child_max_rss()
+{
+    reaped_max_rss = 0;
+}
+
 int try_wait_process(pid_t pid)
 {
     int status;
@@ -110,8 +228,27 @@ void exit_critical_section()
     sigprocmask(SIG_UNBLOCK, &sigs, NULL);
 }
 
+/* Waits within a critical section until a signal was handled, e.g. a child
+ * reaped. The signals the section blocks are let through only while waiting,
+ * so that none arrives unnoticed between checking for it and waiting. */
+void wait_for_signal()
+{
+    sigset_t sigs;
+    sigprocmask(SIG_BLOCK, NULL, &sigs);
+    sigdelset(&sigs, SIGCHLD);
+    sigdelset(&sigs, SIGINT);
+
+    sigsuspend(&sigs);
+}
+
+static void trap_handler(int sig)
+{
+    pending[sig] = 1;
+}
+
 static void sig_handler(int sig)
 {
+    if (trapped[sig]) pending[sig] = 1;
     if (!parser) return;
     parser_kill((void*)parser, sig);
 }
@@ -119,25 +256,35 @@ static void sig_handler(int sig)
 
 static void sigstop_handler(int sig)
 {
+    if (trapped[sig]) pending[sig] = 1;
     if (!parser) return;
     parser_stop((void*)parser, sig);
 }
 
 static void sigchld_handler(int sig)
 {
-    (void)sig;
+    if (trapped[sig]) pending[sig] = 1;
     if (!parser) return;
     
     const int ALL = -1;
     int status;
+    struct rusage usage;
 
     // reap all zombies
     for (;;) {
-        int res = waitpid(ALL, &status, WNOHANG);
-        if (WIFEXITED(status) && res > 0)
-            parser_child_reaped((void*)parser, res);
-        else 
+        int res = wait4(ALL, &status, WNOHANG, &usage);
+        if (res <= 0)
             break;
+
+        reaped_user_usec += usage.ru_utime.tv_sec * 1000000L + usage.ru_utime.tv_usec;
+        reaped_sys_usec += usage.ru_stime.tv_sec * 1000000L + usage.ru_stime.tv_usec;
+        if (usage.ru_maxrss > reaped_max_rss)
+            reaped_max_rss = usage.ru_maxrss;
+
+        if (WIFEXITED(status))
+            parser_child_reaped((void*)parser, res, WEXITSTATUS(status));
+        else if (WIFSIGNALED(status))
+            parser_child_reaped((void*)parser, res, 128 + WTERMSIG(status));
     }
 
 }
@@ -1,4 +1,4 @@
-use std::{io::{stdout, Write}, collections::VecDeque};
+use std::{io::{self, stdout, IsTerminal, Read, Write}, collections::{HashMap, VecDeque}, fs::File, mem::ManuallyDrop, os::fd::FromRawFd, sync::{Mutex, MutexGuard, PoisonError}};
 
 pub use crossterm::{
     cursor,
@@ -8,14 +8,20 @@ pub use crossterm::{
     Command, Result,
 };
 
+use itertools::Itertools;
+
 use crate::{
     completions,
     config,
+    line_editor::{Action, Editor, LineBuffer, ViMode},
     tag,
 };
 
 
 
+static EDITOR: Mutex<Editor> = Mutex::new(Editor::new());
+
+
 pub struct Termio;
 
 impl<'a> config::Configurable<'a> for Termio {
@@ -34,23 +40,118 @@ impl<'a> config::Configurable<'a> for Termio {
 }
 
 
-pub fn prompt_for_input(prompt: &str, retain: bool) -> Result<String> {
+/// Reads a line after `prompt` with the line editor, starting out with `default`.
+/// Hidden input isn't echoed, and is `default` if left empty. Without a terminal,
+/// a line of stdin is read instead.
+pub fn prompt_for_input(prompt: &str, default: Option<&str>, hidden: bool) -> Result<String> {
+    let line = if io::stdin().is_terminal() {
+        print!("{}", prompt);
+        std::io::stdout().flush()?;
+
+        edit_line(default.filter(|_| !hidden), &mut VecDeque::new(), true, None, hidden)?
+    } else {
+        eprint!("{}", prompt);
+        read_stdin_line()?
+    };
+
+    match default {
+        Some(default) if line.is_empty() && hidden => Ok(default.to_string()),
+        _ => Ok(line),
+    }
+}
+
+/// Reads a line of stdin byte by byte, leaving the rest to whoever reads next.
+fn read_stdin_line() -> Result<String> {
+    let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    loop {
+        match stdin.read(&mut byte) {
+            Ok(0) if line.is_empty() => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+/// Asks a yes/no question, returning whether it was answered with yes. Enter
+/// gives the `default` answer.
+pub fn confirm(prompt: &str, default: bool) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        eprint!("{}", prompt);
+        return Ok(match read_stdin_line()?.trim().to_lowercase().as_str() {
+            "y" | "yes" => true,
+            "n" | "no" => false,
+            _ => default,
+        });
+    }
+
     print!("{}", prompt);
-    std::io::stdout().flush()?;
+    stdout().flush()?;
 
-    let res = get_line(None, &mut VecDeque::new(), retain);
+    crossterm::terminal::enable_raw_mode()?;
+    let answer = loop {
+        let key = get_key()?;
+        if is_cancel(&key) {
+            break None;
+        }
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') => break Some(true),
+            KeyCode::Char('n') | KeyCode::Char('N') => break Some(false),
+            KeyCode::Enter => break Some(default),
+            _ => (),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
 
-    res
+    match answer {
+        Some(answer) => {
+            println!("{}", if answer { "y" } else { "n" });
+            Ok(answer)
+        },
+        None => {
+            println!();
+            Err(cancelled())
+        }
+    }
+}
+
+/// Asks for a number until one within `min` and `max` is entered.
+pub fn prompt_for_number(prompt: &str, default: Option<f64>, min: Option<f64>, max: Option<f64>) -> Result<f64> {
+    let default = default.map(|n| n.to_string());
+
+    loop {
+        let answer = prompt_for_input(prompt, default.as_deref(), false)?;
+        match answer.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() && min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max) => return Ok(n),
+            _ => eprintln!("{}", match (min, max) {
+                (Some(min), Some(max)) => format!("Expected a number from {} to {}", min, max),
+                (Some(min), None) => format!("Expected a number of at least {}", min),
+                (None, Some(max)) => format!("Expected a number of at most {}", max),
+                (None, None) => "Expected a number".to_string(),
+            }),
+        }
+    }
 }
 
 fn get_input() -> Result<KeyCode> {
+    Ok(get_key()?.code)
+}
+
+fn get_key() -> Result<KeyEvent> {
     
     loop {
         let byte = event::read();
 
         if let Ok(c) = byte {
             match c {
-                Event::Key(c) => return Ok(c.code),
+                Event::Key(c) => return Ok(c),
                 _ => (),
             }
         } else {
@@ -59,128 +160,113 @@ fn get_input() -> Result<KeyCode> {
     }
 }
 
-pub fn get_line(start_string: Option<&str>, history: &mut VecDeque<String>, retain: bool) -> Result<String> {
+/// The error of a menu or prompt left with Esc or Ctrl-C.
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "cancelled")
+}
+
+fn is_cancel(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Esc
+        || (key.modifiers.contains(KeyModifiers::CONTROL) && matches!(key.code, KeyCode::Char('c') | KeyCode::Char('d')))
+}
+
+pub fn get_line(start_string: Option<&str>, history: &mut VecDeque<String>, retain: bool, abbreviations: Option<&HashMap<String, String>>) -> Result<String> {
+    edit_line(start_string, history, retain, abbreviations, false)
+}
+
+/// The line editor behind `get_line`, which doesn't echo the line if `hidden`.
+fn edit_line(start_string: Option<&str>, history: &mut VecDeque<String>, retain: bool, abbreviations: Option<&HashMap<String, String>>, hidden: bool) -> Result<String> {
     crossterm::terminal::enable_raw_mode()?;
 
-    let mut string = start_string.unwrap_or("").to_string();
+    let mut editor = editor();
+    let mut buffer = LineBuffer::new(start_string.unwrap_or(""));
+    editor.start_line(&buffer);
     let mut start_position = cursor::position().unwrap(); 
-    let mut internal_cursor_pos: u16 = string.len() as u16;
-    let mut visual_cursor_pos: u16 = string.len() as u16;
     let mut history_index = 0;
     let mut clear_all = false;
     
-    history.push_front(string.clone());
-
-    loop {
-        if string == "!!" {
-            string = 
-                if let Ok(mut s) = multiline_edit() {
-                    if s != "!" {
-                        break;
-                    }
-                    s.pop();
-                    visual_cursor_pos = visual_cursor_pos.saturating_sub(2);
-                    internal_cursor_pos = internal_cursor_pos.saturating_sub(2);
-                    s
-                } else {
-                    string
-                }
+    history.push_front(buffer.line.clone());
+
+    let ended = loop {
+        if buffer.line == "!!" && !hidden {
+            match multiline_edit() {
+                Ok(s) if s != "!" => {
+                    buffer.set(&s);
+                    break Ok(());
+                },
+                _ => buffer.set(""),
+            }
         }
-        show_string(&string, start_position, visual_cursor_pos, clear_all)?;
+        show_line(&editor, &buffer, start_position, clear_all, hidden)?;
         stdout().flush()?;
 
+        let line = buffer.line.clone();
+        let indicator = editor.line_prefix();
         clear_all = false;
 
-        let inp = match get_input()? {
-            KeyCode::Char(c) => {
-                clear_all = true;
-                Some(c)
-            },
-            KeyCode::Backspace => {
-                if internal_cursor_pos > 0 {
-                    let removed = string.remove(string.floor_char_boundary((internal_cursor_pos - 1) as usize) as usize);
-                    internal_cursor_pos = internal_cursor_pos.saturating_sub(removed.len_utf8() as u16);
-                    visual_cursor_pos = visual_cursor_pos.saturating_sub(1);
+        match editor.handle(&mut buffer, get_key()?) {
+            Action::Edit => (),
+            Action::Insert(c) => {
+                if c == ' ' {
+                    apply_abbreviation(&mut buffer, abbreviations);
                 }
-                None
-            },
-            KeyCode::Left => {
-                internal_cursor_pos = string.floor_char_boundary(internal_cursor_pos.saturating_sub(1) as usize) as u16;
-                visual_cursor_pos = visual_cursor_pos.saturating_sub(1);
-                None
-            },
-            KeyCode::Right => {
-                sat_add(&mut internal_cursor_pos, 1, string.len() as u16);
-                internal_cursor_pos = string.ceil_char_boundary(internal_cursor_pos as usize) as u16;
-                sat_add(&mut visual_cursor_pos, 1, string.chars().count() as u16);
-                None
+                buffer.insert(c.encode_utf8(&mut [0; 4]));
             },
-            KeyCode::Enter => {
-                break;
-            },
-            KeyCode::Delete => {
-                if string.len() > 0 && internal_cursor_pos < (string.len() as u16) {
-                    string.remove(internal_cursor_pos as usize);
+            Action::Accept => {
+                if apply_abbreviation(&mut buffer, abbreviations) {
+                    show_line(&editor, &buffer, start_position, true, hidden)?;
                 }
-                None
-            },
-            KeyCode::Home => {
-                visual_cursor_pos = 0;
-                internal_cursor_pos = 0;
-                None
-            },
-            KeyCode::End => {
-                visual_cursor_pos = string.chars().count() as u16;
-                internal_cursor_pos = string.len() as u16;
-                None
+                break Ok(());
             },
-            KeyCode::Up => {
-                sat_add_usize(&mut history_index, 1, history.len() - 1);
-                string = history.get(history_index).expect("Index error in history").to_string();
-                visual_cursor_pos = string.chars().count() as u16;
-                internal_cursor_pos = string.len() as u16;
-
-                None
-            },
-            KeyCode::Down => {
-                history_index = history_index.saturating_sub(1);
-                string = history.get(history_index).expect("Index error in history").to_string();
-                visual_cursor_pos = string.chars().count() as u16;
-                internal_cursor_pos = string.len() as u16;
-
-                None
-            },
-            KeyCode::Tab => {
-                let possibilities = completions::get_possibilities(&string, internal_cursor_pos);
+            Action::Complete if !hidden => {
+                let possibilities = completions::get_possibilities(&buffer.line, buffer.cursor as u16);
 
                 if possibilities.2.len() == 1 {
                     let (to_replace, prefix, completion) = (possibilities.0, possibilities.1, &possibilities.2[0]);
-                    let p = string.floor_char_boundary(internal_cursor_pos as usize - to_replace.len());
-                    string.replace_range(p .. string.ceil_char_boundary(p + to_replace.len()), &format!("{}{}", prefix, completion));
-                    visual_cursor_pos = string.chars().count() as u16;
-                    internal_cursor_pos = string.len() as u16;
+                    let p = buffer.line.floor_char_boundary(buffer.cursor - to_replace.len());
+                    buffer.remove(p .. buffer.line.ceil_char_boundary(p + to_replace.len()));
+                    buffer.insert(&format!("{}{}", prefix, completion));
                 } else {
-                    start_position.1 = start_position.1.saturating_sub(show_possibilities(&possibilities.2, calc_cursor_screen_pos(start_position, visual_cursor_pos)) as u16);
+                    let cursor_pos = (indicator.chars().count() + buffer.visual_cursor()) as u16;
+                    start_position.1 = start_position.1.saturating_sub(show_possibilities(&possibilities.2, calc_cursor_screen_pos(start_position, cursor_pos)) as u16);
                 }
-
-                None
             },
-            _ => None
+            Action::Complete => (),
+            Action::HistoryPrevious => {
+                sat_add_usize(&mut history_index, 1, history.len() - 1);
+                buffer.set(history.get(history_index).expect("Index error in history"));
+            },
+            Action::HistoryNext => {
+                history_index = history_index.saturating_sub(1);
+                buffer.set(history.get(history_index).expect("Index error in history"));
+            },
+            Action::ClearScreen => {
+                // Scroll the line being edited to the top
+                queue!(stdout(), terminal::ScrollUp(start_position.1))?;
+                start_position.1 = 0;
+                clear_all = true;
+            },
+            Action::Eof => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Action::Interrupt => {
+                buffer.cursor = buffer.line.len();
+                show_line(&editor, &buffer, start_position, false, hidden)?;
+                queue!(stdout(), style::Print("^C"))?;
+                break Err(cancelled());
+            },
         };
 
-        if let Some(inp) = inp {
-            internal_cursor_pos = insert_byte_aligned(&mut string, inp, internal_cursor_pos);
-            internal_cursor_pos += inp.len_utf8() as u16;
-            visual_cursor_pos += 1;
-        }
+        clear_all |= buffer.line != line || editor.line_prefix() != indicator;
 
         if history_index == 0 {
             if let Some(front) = history.get_mut(0) {
-                *front = string.clone();
+                *front = buffer.line.clone();
             }
         }
-    }
+    };
     
+    if editor.vi_mode().is_some() {
+        queue!(stdout(), cursor::SetCursorStyle::DefaultUserShape)?;
+    }
     if retain {
         execute!(stdout(), style::Print(format!("\r\n")))?;
     } else {
@@ -192,11 +278,50 @@ pub fn get_line(start_string: Option<&str>, history: &mut VecDeque<String>, reta
     crossterm::terminal::disable_raw_mode()?;
 
     history.pop_front();
-    if !string.is_empty() {
-        history.push_front(string.clone());
+    if ended.is_ok() && !buffer.line.is_empty() && !hidden {
+        history.push_front(buffer.line.clone());
+    }
+
+    ended.map(|_| buffer.line)
+}
+
+/// The line editor's state shared by all lines, e.g. its kill ring.
+pub fn editor() -> MutexGuard<'static, Editor> {
+    EDITOR.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn apply_abbreviation(buffer: &mut LineBuffer, abbreviations: Option<&HashMap<String, String>>) -> bool {
+    match abbreviations.and_then(|a| expand_abbreviation(&mut buffer.line, buffer.cursor, a)) {
+        Some(pos) => {
+            buffer.cursor = pos;
+            true
+        },
+        None => false
+    }
+}
+
+/// Expands the abbreviation ending at `cursor` if it is in command position,
+/// returning the new cursor position.
+fn expand_abbreviation(string: &mut String, cursor: usize, abbreviations: &HashMap<String, String>) -> Option<usize> {
+    if string[cursor..].chars().next().is_some_and(|c| !c.is_whitespace()) {
+        return None;
+    }
+
+    let word_start = string[..cursor]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let expansion = abbreviations.get(&string[word_start..cursor])?.clone();
+
+    let before = string[..word_start].trim_end();
+    if !before.is_empty() && !["|", "&&", ";"].iter().any(|s| before.ends_with(s)) {
+        return None;
     }
 
-    Ok(string)
+    string.replace_range(word_start..cursor, &expansion);
+
+    Some(word_start + expansion.len())
 }
 
 fn calc_cursor_screen_pos(start_position: (u16, u16), cursor_pos: u16) -> (u16, u16) {
@@ -208,6 +333,28 @@ fn calc_cursor_screen_pos(start_position: (u16, u16), cursor_pos: u16) -> (u16,
     (cursor_col, cursor_row)
 }
 
+/// Shows the line after the vi mode indicator, highlighting the selection in
+/// visual mode.
+fn show_line(editor: &Editor, buffer: &LineBuffer, start_position: (u16, u16), clear_all: bool, hidden: bool) -> Result<()> {
+    if hidden {
+        return show_string("", start_position, 0, clear_all);
+    }
+
+    let line = &buffer.line;
+    let shown = match editor.selection(buffer) {
+        Some(range) => format!("{}{}{}", &line[..range.start], style::Stylize::reverse(&line[range.clone()]), &line[range.end..]),
+        None => line.clone(),
+    };
+    match editor.vi_mode() {
+        Some(ViMode::Insert) => queue!(stdout(), cursor::SetCursorStyle::SteadyBar)?,
+        Some(_) => queue!(stdout(), cursor::SetCursorStyle::SteadyBlock)?,
+        None => (),
+    }
+
+    let indicator = editor.line_prefix();
+    show_string(&format!("{}{}", indicator, shown), start_position, (indicator.chars().count() + buffer.visual_cursor()) as u16, clear_all)
+}
+
 fn show_string(string: &str, start_position: (u16, u16), cursor_pos: u16, clear_all: bool) -> Result<()> {
     
     let (cursor_col, cursor_row) = calc_cursor_screen_pos(start_position, cursor_pos);
@@ -292,10 +439,6 @@ fn show_possibilities(strings: &[String], cursor_position: (u16, u16)) -> u16 {
     lines_shifted
 }
 
-fn sat_add(lhs: &mut u16, rhs: u16, upper_bound: u16) {
-    *lhs += if (*lhs + rhs) > upper_bound { 0 } else { rhs } 
-}
-
 fn sat_add_usize(lhs: &mut usize, rhs: usize, upper_bound: usize) {
     *lhs += if (*lhs + rhs) > upper_bound { 0 } else { rhs } 
 }
@@ -303,81 +446,168 @@ fn sat_add_usize(lhs: &mut usize, rhs: usize, upper_bound: usize) {
 
 
 
-pub fn get_choice(options: &[&str], retain: bool) -> Result<usize> {
+/// Lets the user pick one of `options`, numbered from `start`, with the arrow
+/// keys or its number, failing with `ErrorKind::Interrupted` if the menu is
+/// cancelled.
+pub fn get_choice(options: &[&str], start: usize, retain: bool) -> Result<usize> {
+    match menu(options, start, false, false, retain)? {
+        Some(chosen) => Ok(chosen[0]),
+        None => Err(cancelled()),
+    }
+}
 
-    crossterm::terminal::enable_raw_mode()?;
-    let mut choice: usize = 0;
+/// Lets the user pick from `options`, returning the chosen indices or `None`
+/// if cancelled. With `multi`, Space toggles options and Enter accepts the
+/// toggled ones, or else the highlighted one. With `fuzzy`, typing filters
+/// the options instead of choosing them by number.
+pub fn choose(options: &[&str], multi: bool, fuzzy: bool) -> Result<Option<Vec<usize>>> {
+    menu(options, 0, multi, fuzzy, false)
+}
 
-    let start_pos = cursor::position()?;
+/// The menu behind `get_choice` and `choose`, numbering the options from `start`.
+/// A number is chosen once no further digit could make it another option's,
+/// until then the option typed so far is highlighted.
+fn menu(options: &[&str], start: usize, multi: bool, fuzzy: bool, retain: bool) -> Result<Option<Vec<usize>>> {
+    if options.is_empty() {
+        return Ok(None);
+    }
 
-    loop {
-        execute!(
-            stdout(),
-            cursor::MoveTo(start_pos.0, start_pos.1)
-        )?;
-        for opt in 0..options.len() {
-            if choice == opt {
-                queue!(
-                    stdout(),
-                    style::SetAttribute(style::Attribute::Bold),
-                )?;
-            } else {
-                queue!(
-                    stdout(),
-                    style::SetAttribute(style::Attribute::NormalIntensity)
-                )?;
-            }
+    crossterm::terminal::enable_raw_mode()?;
+    let mut query = String::new();
+    let mut toggled = vec![false; options.len()];
+    let mut choice: usize = 0;
+    let mut number = String::new();
+    let mut lines;
+
+    let chosen = loop {
+        let visible: Vec<usize> = if fuzzy { fuzzy_filter(&query, options) } else { (0..options.len()).collect() };
+        choice = choice.min(visible.len().saturating_sub(1));
+
+        // Only as many options as fit on the screen, scrolling with the choice
+        let rows = terminal::size().map_or(20, |(_, h)| h as usize).saturating_sub(1 + usize::from(fuzzy)).max(1);
+        let first = (choice + 1).saturating_sub(rows);
+
+        queue!(stdout(), cursor::MoveToColumn(0), terminal::Clear(ClearType::FromCursorDown))?;
+        lines = 0;
+        if fuzzy {
+            queue!(stdout(), style::Print(format!("> {}\r\n", query)))?;
+            lines += 1;
+        }
+        for (row, &opt) in visible.iter().enumerate().skip(first).take(rows) {
+            let mark = match (multi, toggled[opt]) {
+                (false, _) => "",
+                (true, false) => "[ ] ",
+                (true, true) => "[x] ",
+            };
             queue!(
                 stdout(),
-                style::Print(format!("{}. {}\r\n", opt, options[opt]))
+                style::SetAttribute(if row == choice { style::Attribute::Bold } else { style::Attribute::NormalIntensity }),
+                style::Print(format!("{}{}. {}\r\n", mark, start + opt, options[opt]))
             )?;
+            lines += 1;
+        }
+        queue!(stdout(), style::SetAttribute(style::Attribute::NormalIntensity), cursor::MoveUp(lines))?;
+        if fuzzy {
+            queue!(stdout(), cursor::MoveToColumn(2 + query.chars().count() as u16))?;
         }
-
         stdout().flush()?;
 
-        match get_input()? {
+        let key = get_key()?;
+        if is_cancel(&key) {
+            break None;
+        }
+        if fuzzy || !matches!(key.code, KeyCode::Char(c) if c.is_ascii_digit()) {
+            number.clear();
+        }
+        match key.code {
             KeyCode::Up => {
                 choice = choice.saturating_sub(1);
             },
-            KeyCode::Down => {
-                sat_add_usize(&mut choice, 1, options.len() - 1);
+            KeyCode::Down | KeyCode::Tab => {
+                sat_add_usize(&mut choice, 1, visible.len().saturating_sub(1));
             },
-            KeyCode::Enter => {
-                break;
+            KeyCode::Enter if !visible.is_empty() => {
+                let selection: Vec<usize> = (0..options.len()).filter(|&opt| toggled[opt]).collect();
+                break Some(if selection.is_empty() { vec![visible[choice]] } else { selection });
             },
-            KeyCode::Char(c) => {
-                if let Some(d) = c.to_digit(10) {
-                    if d < options.len() as u32 {
-                        return Ok(d as usize);
+            KeyCode::Char(' ') if multi && !visible.is_empty() => {
+                toggled[visible[choice]] ^= true;
+            },
+            KeyCode::Char(c) if fuzzy => {
+                query.push(c);
+                choice = 0;
+            },
+            KeyCode::Backspace if fuzzy => {
+                query.pop();
+            },
+            KeyCode::Char(c) if c.is_ascii_digit() => {
+                number.push(c);
+                let n: usize = number.parse().unwrap_or(usize::MAX);
+                let opt = n.checked_sub(start).filter(|&opt| opt < options.len());
+                if let Some(opt) = opt {
+                    choice = opt;
+                }
+
+                if n == 0 || n.saturating_mul(10) >= start + options.len() {
+                    number.clear();
+                    match opt {
+                        Some(opt) if !multi => break Some(vec![opt]),
+                        Some(opt) => toggled[opt] ^= true,
+                        None => (),
                     }
                 }
-            }
+            },
             _ => (),
-        };   
-    }
+        };
+    };
 
-    if !retain {
-        queue!(
-            stdout(),
-            cursor::MoveUp(options.len() as u16),
-            terminal::Clear(ClearType::FromCursorDown)
-        )?;
+    if retain {
+        queue!(stdout(), cursor::MoveDown(lines), cursor::MoveToColumn(0))?;
+    } else {
+        queue!(stdout(), cursor::MoveToColumn(0), terminal::Clear(ClearType::FromCursorDown))?;
     }
-
-    execute!(
-        stdout(),
-        style::SetAttribute(style::Attribute::NormalIntensity)
-    )?;
+    stdout().flush()?;
 
     crossterm::terminal::disable_raw_mode()?;
 
-    Ok(choice)
+    Ok(chosen)
+}
+
+/// The indices of the `options` containing the characters of `query` in
+/// order, ignoring case, with the most compact matches first.
+fn fuzzy_filter(query: &str, options: &[&str]) -> Vec<usize> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+
+    options.iter()
+        .enumerate()
+        .filter_map(|(i, opt)| fuzzy_span(&query, &opt.to_lowercase().chars().collect::<Vec<_>>()).map(|span| (span, i)))
+        .sorted()
+        .map(|(_, i)| i)
+        .collect()
+}
+
+/// The length of the shortest part of `option` containing `query` in order.
+fn fuzzy_span(query: &[char], option: &[char]) -> Option<usize> {
+    let Some(first) = query.first() else {
+        return Some(0);
+    };
+
+    (0..option.len())
+        .filter(|&start| option[start] == *first)
+        .filter_map(|start| {
+            let mut matched = 0;
+            option[start..].iter().position(|c| {
+                matched += usize::from(*c == query[matched]);
+                matched == query.len()
+            }).map(|end| end + 1)
+        })
+        .min()
 }
 
 
 
 pub fn edit_command(command: &mut String) -> Result<()> {
-    *command = get_line(Some(command), &mut VecDeque::new(), true)?;
+    *command = get_line(Some(command), &mut VecDeque::new(), true, None)?;
 
     Ok(())
 }
@@ -391,15 +621,34 @@ fn multiline_edit() -> Result<String> {
 }
 
 
-fn insert_byte_aligned(string: &mut String, c: char, internal_pos: u16) -> u16 {
-    let mut internal_pos = internal_pos as usize;
 
-    if !string.is_char_boundary(internal_pos) {
-        internal_pos = string.floor_char_boundary(internal_pos);
-    }
+#[test]
+fn test_expand_abbreviation() {
+    let abbreviations: HashMap<String, String> = [
+        ("gco", "git checkout"),
+        ("l", "ls -la"),
+    ].into_iter().map(|(n, e)| (n.to_string(), e.to_string())).collect();
+
+    let mut line = "gco".to_string();
+    assert_eq!(expand_abbreviation(&mut line, 3, &abbreviations), Some(12));
+    assert_eq!(line, "git checkout");
 
-    string.insert(internal_pos, c);
+    let mut line = "cd /tmp && l".to_string();
+    assert_eq!(expand_abbreviation(&mut line, 12, &abbreviations), Some(17));
+    assert_eq!(line, "cd /tmp && ls -la");
 
-    internal_pos as u16
+    let mut line = "echo gco".to_string();
+    assert_eq!(expand_abbreviation(&mut line, 8, &abbreviations), None);
+
+    let mut line = "gcox".to_string();
+    assert_eq!(expand_abbreviation(&mut line, 3, &abbreviations), None);
 }
 
+#[test]
+fn test_fuzzy_filter() {
+    let options = ["git checkout", "go", "gcc", "cargo check"];
+    assert_eq!(fuzzy_filter("gc", &options), [2, 3, 0]);
+    assert_eq!(fuzzy_filter("CHE", &options), [0, 3]);
+    assert_eq!(fuzzy_filter("", &options), [0, 1, 2, 3]);
+    assert!(fuzzy_filter("xyz", &options).is_empty());
+}
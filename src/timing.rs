@@ -0,0 +1,151 @@
+use std::{
+    ffi::c_long,
+    time::Instant,
+};
+
+
+extern "C" {
+    fn child_usage(user_usec: *mut c_long, sys_usec: *mut c_long, max_rss_kb: *mut c_long);
+    fn reset_child_max_rss();
+}
+
+pub const TIMEFORMAT_KEY: &str = "TIMEFORMAT";
+/// Like bash's, with the max RSS added.
+pub const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS\nmaxrss\t%MK";
+/// Used by `time -p`.
+pub const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+
+/// Resources used by a job.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JobTimes {
+    /// Wall clock seconds.
+    pub real: f64,
+    /// CPU seconds spent in user mode.
+    pub user: f64,
+    /// CPU seconds spent in the kernel.
+    pub sys: f64,
+    /// Largest resident set size of any of its processes, in KB.
+    pub max_rss: i64,
+}
+
+impl JobTimes {
+    /// Formats the times like bash's `TIMEFORMAT`: `%[p][l]R`, `%[p][l]U` and
+    /// `%[p][l]S` are the real, user and system seconds with `p` decimals and
+    /// in `MmS.FFs` form with `l`, `%P` is the CPU percentage, `%M` the max
+    /// RSS and `%%` a literal `%`.
+    pub fn format(&self, format: &str) -> String {
+        let mut out = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+
+            let precision = chars.next_if(|c| c.is_ascii_digit())
+                .and_then(|p| p.to_digit(10))
+                .map(|p| p.min(3) as usize)
+                .unwrap_or(3);
+            let long = chars.next_if_eq(&'l').is_some();
+
+            let secs = match chars.next() {
+                Some('R') => self.real,
+                Some('U') => self.user,
+                Some('S') => self.sys,
+                Some('P') => {
+                    let cpu = if self.real > 0.0 { (self.user + self.sys) / self.real * 100.0 } else { 0.0 };
+                    out.push_str(&format!("{:.2}", cpu));
+                    continue;
+                },
+                Some('M') => {
+                    out.push_str(&self.max_rss.to_string());
+                    continue;
+                },
+                Some('%') => {
+                    out.push('%');
+                    continue;
+                },
+                Some(c) => {
+                    out.push('%');
+                    out.push(c);
+                    continue;
+                },
+                None => {
+                    out.push('%');
+                    break;
+                }
+            };
+
+            if long {
+                let minutes = (secs / 60.0).floor();
+                out.push_str(&format!("{}m{:.*}s", minutes, precision, secs - minutes * 60.0));
+            } else {
+                out.push_str(&format!("{:.*}", precision, secs));
+            }
+        }
+
+        out
+    }
+}
+
+/// Measures the resources used by the jobs run while it exists.
+pub struct Timer {
+    start: Instant,
+    user_usec: c_long,
+    sys_usec: c_long,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        let (user_usec, sys_usec, _) = usage();
+        unsafe { reset_child_max_rss(); }
+
+        Self { start: Instant::now(), user_usec, sys_usec }
+    }
+
+    /// The resources used since the timer started. Only children that were
+    /// already reaped are accounted for.
+    pub fn elapsed(&self) -> JobTimes {
+        let (user_usec, sys_usec, max_rss) = usage();
+
+        JobTimes {
+            real: self.start.elapsed().as_secs_f64(),
+            user: (user_usec - self.user_usec) as f64 / 1e6,
+            sys: (sys_usec - self.sys_usec) as f64 / 1e6,
+            max_rss,
+        }
+    }
+}
+
+fn usage() -> (c_long, c_long, c_long) {
+    let (mut user_usec, mut sys_usec, mut max_rss) = (0, 0, 0);
+    unsafe { child_usage(&mut user_usec, &mut sys_usec, &mut max_rss); }
+    (user_usec, sys_usec, max_rss)
+}
+
+/// Short human readable form of a duration, e.g. `850ms`, `3.2s` or `1m5s`.
+pub fn format_duration(secs: f64) -> String {
+    if secs < 1.0 {
+        format!("{}ms", (secs * 1000.0) as u64)
+    } else if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else if secs < 60.0 * 60.0 {
+        format!("{}m{}s", (secs / 60.0) as u64, secs as u64 % 60)
+    } else {
+        format!("{}h{}m", (secs / 3600.0) as u64, secs as u64 % 3600 / 60)
+    }
+}
+
+
+#[test]
+fn test_format() {
+    let times = JobTimes { real: 61.5, user: 0.25, sys: 0.0125, max_rss: 2048 };
+
+    assert_eq!(times.format(DEFAULT_TIMEFORMAT), "\nreal\t1m1.500s\nuser\t0m0.250s\nsys\t0m0.013s\nmaxrss\t2048K");
+    assert_eq!(times.format(POSIX_TIMEFORMAT), "real 61.50\nuser 0.25\nsys 0.01");
+    assert_eq!(times.format("%0R %P%% %x"), "62 0.43% %x");
+    assert_eq!(format_duration(0.85), "850ms");
+    assert_eq!(format_duration(65.0), "1m5s");
+}
@@ -0,0 +1,96 @@
+use std::ffi::{CStr, CString};
+
+
+extern "C" {
+    fn signal_from_name(name: *const std::ffi::c_char) -> i32;
+    fn signal_to_name(sig: i32) -> *const std::ffi::c_char;
+    fn trap_signal(sig: i32) -> i32;
+    fn ignore_signal(sig: i32) -> i32;
+    fn reset_signal(sig: i32) -> i32;
+    fn take_pending_signal() -> i32;
+}
+
+/// Run when the shell exits.
+pub const EXIT: &str = "EXIT";
+/// Run after a command fails.
+pub const ERR: &str = "ERR";
+
+const MAX_SIGNAL: i32 = 64;
+
+
+/// Normalizes a trap condition given as e.g. `INT`, `SIGINT`, `int` or `2`
+/// to its name without the `SIG` prefix.
+pub fn condition_name(condition: &str) -> Option<String> {
+    if let Ok(n) = condition.parse::<i32>() {
+        return if n == 0 { Some(EXIT.to_string()) } else { signal_name(n) };
+    }
+
+    let upper = condition.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+    if name == EXIT || name == ERR || signal_number(name).is_some() {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+pub fn signal_number(name: &str) -> Option<i32> {
+    let name = CString::new(name).ok()?;
+    let sig = unsafe { signal_from_name(name.as_ptr()) };
+    (sig > 0).then_some(sig)
+}
+
+pub fn signal_name(sig: i32) -> Option<String> {
+    let name = unsafe { signal_to_name(sig) };
+    if name.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(name) }.to_string_lossy().to_string())
+    }
+}
+
+/// All known signals as `(number, name)`.
+pub fn signals() -> Vec<(i32, String)> {
+    (1..=MAX_SIGNAL).filter_map(|sig| signal_name(sig).map(|n| (sig, n))).collect()
+}
+
+/// Starts catching the signal `name` so that its trap runs. Pseudo-signals
+/// like `EXIT` need no handler.
+pub fn catch(name: &str) {
+    if let Some(sig) = signal_number(name) {
+        unsafe { trap_signal(sig); }
+    }
+}
+
+pub fn ignore(name: &str) {
+    if let Some(sig) = signal_number(name) {
+        unsafe { ignore_signal(sig); }
+    }
+}
+
+/// Restores the default handling of the signal `name`.
+pub fn reset(name: &str) {
+    if let Some(sig) = signal_number(name) {
+        unsafe { reset_signal(sig); }
+    }
+}
+
+/// Returns the name of a caught signal whose trap hasn't run yet.
+pub fn take_pending() -> Option<String> {
+    match unsafe { take_pending_signal() } {
+        0 => None,
+        sig => signal_name(sig),
+    }
+}
+
+
+#[test]
+fn test_condition_name() {
+    assert_eq!(condition_name("int"), Some("INT".to_string()));
+    assert_eq!(condition_name("SIGTERM"), Some("TERM".to_string()));
+    assert_eq!(condition_name("0"), Some(EXIT.to_string()));
+    assert_eq!(condition_name("15"), Some("TERM".to_string()));
+    assert_eq!(condition_name("ERR"), Some(ERR.to_string()));
+    assert_eq!(condition_name("NOPE"), None);
+}